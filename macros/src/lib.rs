@@ -1,5 +1,8 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use proc_macro_crate::{crate_name, FoundCrate};
+use quote::{format_ident, quote, ToTokens};
+use syn::parse::{Parse, ParseStream};
 
 /// Convenience macro for writing a slightly nicer endpoint function.
 ///
@@ -8,14 +11,14 @@ use quote::quote;
 /// the lifetime bounds being impossible to express.
 ///
 /// This macro get around this by enabling writing an endpoint as:
-/// ```
+/// ```ignore
 /// #[endpoint]
 /// async fn my_endpoint(req: &mut Request) -> Result<impl Responder, MyError> {
 ///     Ok("hello, world!")
 /// }
 /// ```
 /// which is transformed into something like:
-/// ```
+/// ```ignore
 /// async fn my_endpoint(mut req: Request) -> Request {
 ///     async fn my_endpoint(req: &mut Request) -> Result<impl Responder, MyError> {
 ///         Ok("hello, world!")
@@ -29,25 +32,513 @@ use quote::quote;
 ///     req
 /// }
 /// ```
+///
+/// Endpoints can also take any number of arguments that implement
+/// `atium::extract::FromRequest`, which are extracted from the request in order
+/// before the endpoint is called. If one can't be extracted, its rejection is used as
+/// the response instead. The request itself can still be taken as `&mut Request`
+/// or `&Request`, in any position:
+/// ```ignore
+/// #[endpoint]
+/// async fn update_user(
+///     Path(id): Path<u64>,
+///     Json(user): Json<User>,
+///     req: &mut Request,
+/// ) -> Result<impl Responder, Error> {
+///     // ...
+/// }
+/// ```
+///
+/// Generic endpoints are supported, and doc comments, `#[cfg]` and lint attributes
+/// are kept on the generated handler. Endpoints can also be methods taking `&self`
+/// in inherent impls, in which case the generated method takes `&self` and a
+/// `Request`, and can be called from a `Handler` implementation.
+///
+/// Generated code refers to `::atium`, or the name it was renamed to in
+/// `Cargo.toml`. If it's only available under another path, e.g. when re-exported
+/// from another crate, this can be set with `#[endpoint(crate = "path::to::atium")]`.
 #[proc_macro_attribute]
-pub fn endpoint(_: TokenStream, mut item: TokenStream) -> TokenStream {
-    let input: syn::ItemFn = match syn::parse(item.clone()) {
-        Ok(input) => input,
+pub fn endpoint(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand(item, |input| {
+        let args: EndpointArgs = syn::parse(attr)?;
+        expand_endpoint(input, &args.krate)
+    })
+}
+
+/// Parses the item as a function and expands it. On failure, the item is left as it
+/// is alongside the error, so that uses of it don't cause more errors.
+fn expand(
+    item: TokenStream,
+    f: impl FnOnce(syn::ItemFn) -> syn::Result<TokenStream2>,
+) -> TokenStream {
+    match syn::parse(item.clone()).and_then(f) {
+        Ok(output) => TokenStream::from(output),
         Err(e) => {
+            let mut item = item;
             item.extend(TokenStream::from(e.into_compile_error()));
-            return item;
+            item
         }
-    };
+    }
+}
+
+/// The path to the `atium` crate in generated code.
+struct CratePath(syn::Path);
+
+impl Default for CratePath {
+    fn default() -> Self {
+        // Examples and tests in atium itself refer to it as `atium` too, so only
+        // renames are taken from the manifest.
+        let name = match crate_name("atium") {
+            Ok(FoundCrate::Name(name)) => name,
+            _ => "atium".to_owned(),
+        };
+
+        let ident = syn::Ident::new(&name, Span::call_site());
+        CratePath(syn::parse_quote!(::#ident))
+    }
+}
+
+impl CratePath {
+    /// Parses a `crate = "path"` or `crate = path` argument.
+    fn parse_arg(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<syn::Token![crate]>()?;
+        input.parse::<syn::Token![=]>()?;
+
+        if input.peek(syn::LitStr) {
+            input.parse::<syn::LitStr>()?.parse().map(CratePath)
+        } else {
+            input.parse().map(CratePath)
+        }
+    }
+}
+
+impl ToTokens for CratePath {
+    fn to_tokens(&self, tokens: &mut TokenStream2) {
+        self.0.to_tokens(tokens);
+    }
+}
+
+struct EndpointArgs {
+    krate: CratePath,
+}
+
+impl Parse for EndpointArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let krate = match input.is_empty() {
+            true => CratePath::default(),
+            false => CratePath::parse_arg(input)?,
+        };
+
+        input.parse::<Option<syn::Token![,]>>()?;
+        Ok(EndpointArgs { krate })
+    }
+}
+
+fn is_attr(attr: &syn::Attribute, names: &[&str]) -> bool {
+    names.iter().any(|name| attr.path.is_ident(name))
+}
+
+/// Attributes that are copied from an endpoint to the handler generated for it.
+const OUTER_ATTRS: &[&str] = &[
+    "allow",
+    "cfg",
+    "deny",
+    "deprecated",
+    "doc",
+    "forbid",
+    "warn",
+];
+
+/// Attributes that are only kept on the generated handler.
+const HANDLER_ONLY_ATTRS: &[&str] = &["deprecated", "doc"];
+
+fn expand_endpoint(mut input: syn::ItemFn, krate: &CratePath) -> syn::Result<TokenStream2> {
+    if input.sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            input.sig.fn_token,
+            "endpoints must be `async`",
+        ));
+    }
 
     let vis = input.vis.clone();
     let name = input.sig.ident.clone();
 
-    TokenStream::from(quote! {
-        #vis async fn #name(mut req: Request) -> Request {
+    let mut receiver = None;
+    let mut extractors = Vec::new();
+    let mut args = Vec::new();
+    let mut req_arg = None;
+
+    for (i, arg) in input.sig.inputs.iter().enumerate() {
+        let ty = match arg {
+            syn::FnArg::Typed(arg) => &arg.ty,
+            syn::FnArg::Receiver(arg) => {
+                if arg.reference.is_none() || arg.mutability.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        arg,
+                        "endpoint methods must take `&self`",
+                    ));
+                }
+
+                receiver = Some(arg);
+                continue;
+            }
+        };
+
+        // References can only be to the request itself, which is passed through
+        // rather than extracted.
+        if let syn::Type::Reference(reference) = &**ty {
+            if let Some(prev) = req_arg.replace(reference) {
+                let mut err = syn::Error::new_spanned(
+                    reference,
+                    "endpoints can only take one reference to the request",
+                );
+                err.combine(syn::Error::new_spanned(prev, "first reference here"));
+                return Err(err);
+            }
+
+            args.push(match reference.mutability {
+                Some(_) => quote!(&mut req),
+                None => quote!(&req),
+            });
+            continue;
+        }
+
+        let arg = format_ident!("arg{}", i);
+
+        extractors.push(quote! {
+            let #arg = match <#ty as #krate::extract::FromRequest>::from_request(&mut req).await {
+                ::core::result::Result::Ok(value) => value,
+                ::core::result::Result::Err(rejection) => {
+                    #krate::responder::Responder::respond_to(rejection, &mut req).await;
+                    return req;
+                }
+            };
+        });
+        args.push(quote!(#arg));
+    }
+
+    let generics = input.sig.generics.clone();
+    let where_clause = &generics.where_clause;
+
+    // Lifetimes are left for the compiler to infer.
+    let params: Vec<_> = generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            syn::GenericParam::Type(param) => Some(&param.ident),
+            syn::GenericParam::Const(param) => Some(&param.ident),
+            syn::GenericParam::Lifetime(_) => None,
+        })
+        .collect();
+
+    let turbofish = match params.is_empty() {
+        true => quote!(),
+        false => quote!(::<#(#params),*>),
+    };
+
+    let attrs: Vec<_> = input
+        .attrs
+        .iter()
+        .filter(|attr| is_attr(attr, OUTER_ATTRS))
+        .cloned()
+        .collect();
+
+    input
+        .attrs
+        .retain(|attr| !is_attr(attr, HANDLER_ONLY_ATTRS));
+
+    // Methods can't be nested inside the handler like functions can, since they
+    // need `Self`, so they're kept alongside it under another name.
+    if let Some(receiver) = receiver {
+        input.sig.ident = format_ident!("__{}_endpoint", name);
+        input.vis = syn::Visibility::Inherited;
+
+        let endpoint = &input.sig.ident;
+        let self_token = &receiver.self_token;
+
+        return Ok(quote! {
+            #[doc(hidden)]
+            #input
+
+            #(#attrs)*
+            #vis async fn #name #generics (&self, mut req: #krate::Request) -> #krate::Request
+            #where_clause
+            {
+                #(#extractors)*
+                let res = #self_token.#endpoint #turbofish (#(#args),*).await;
+                #krate::responder::Responder::respond_to(res, &mut req).await;
+                req
+            }
+        });
+    }
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis async fn #name #generics (mut req: #krate::Request) -> #krate::Request
+        #where_clause
+        {
             #input
-            let res = #name(&mut req).await;
-            atium::responder::Responder::respond_to(res, &mut req).await;
+            #(#extractors)*
+            let res = #name #turbofish (#(#args),*).await;
+            #krate::responder::Responder::respond_to(res, &mut req).await;
             req
         }
     })
 }
+
+macro_rules! route_macros {
+    ($($name:ident => $method:literal,)*) => {
+        $(
+            #[doc = concat!("Defines an endpoint for `", $method, "` requests to a path.")]
+            ///
+            /// See [`get`] for details.
+            #[proc_macro_attribute]
+            pub fn $name(attr: TokenStream, item: TokenStream) -> TokenStream {
+                route(stringify!($name), attr, item)
+            }
+        )*
+    };
+}
+
+route_macros! {
+    connect => "CONNECT",
+    delete => "DELETE",
+    head => "HEAD",
+    options => "OPTIONS",
+    patch => "PATCH",
+    post => "POST",
+    put => "PUT",
+    trace => "TRACE",
+}
+
+/// Defines an endpoint for `GET` requests to a path.
+///
+/// The function is turned into an [`endpoint`], and replaced with a value of the
+/// same name that can be registered with `Router::service`:
+/// ```ignore
+/// #[get("/users/:id")]
+/// async fn get_user(Path(id): Path<u64>) -> Result<impl Responder, Error> {
+///     // ...
+/// }
+///
+/// let router = Router::new().with(|r| {
+///     r.service(get_user);
+/// });
+/// ```
+///
+/// The crate path can be set as for [`endpoint`], e.g.
+/// `#[get("/", crate = "path::to::atium")]`.
+///
+/// The parameters in the path are checked against the endpoint's `Path` extractor,
+/// if it has one. A tuple must have one element per parameter, and a primitive,
+/// `String` or `Uuid` must be the only parameter. Structs are declared outside of
+/// the endpoint, where the macro can't see them, so their fields aren't checked.
+#[proc_macro_attribute]
+pub fn get(attr: TokenStream, item: TokenStream) -> TokenStream {
+    route("get", attr, item)
+}
+
+fn route(method: &str, attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand(item, |input| {
+        let args: RouteArgs = syn::parse(attr)?;
+        expand_route(method, args, input)
+    })
+}
+
+struct RouteArgs {
+    path: syn::LitStr,
+    krate: CratePath,
+}
+
+impl Parse for RouteArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input
+            .parse()
+            .map_err(|e| syn::Error::new(e.span(), "expected a path, e.g. `\"/users/:id\"`"))?;
+
+        let mut krate = CratePath::default();
+
+        if input.parse::<Option<syn::Token![,]>>()?.is_some() && !input.is_empty() {
+            krate = CratePath::parse_arg(input)?;
+            input.parse::<Option<syn::Token![,]>>()?;
+        }
+
+        Ok(RouteArgs { path, krate })
+    }
+}
+
+fn expand_route(method: &str, args: RouteArgs, input: syn::ItemFn) -> syn::Result<TokenStream2> {
+    let RouteArgs { path, krate } = args;
+
+    if let Some(syn::FnArg::Receiver(receiver)) = input.sig.inputs.first() {
+        return Err(syn::Error::new_spanned(
+            receiver,
+            "route macros can't be used on methods, use `#[endpoint]` instead",
+        ));
+    }
+
+    if let Some(param) = input
+        .sig
+        .generics
+        .params
+        .iter()
+        .find(|param| !matches!(param, syn::GenericParam::Lifetime(_)))
+    {
+        return Err(syn::Error::new_spanned(
+            param,
+            "route macros can't be used on generic functions, use `#[endpoint]` instead",
+        ));
+    }
+
+    let params = path_params(&path)?;
+    check_path_extractor(&input, &path, &params)?;
+
+    let vis = input.vis.clone();
+    let name = input.sig.ident.clone();
+    let method = format_ident!("{}", method);
+
+    let attrs: Vec<_> = input
+        .attrs
+        .iter()
+        .filter(|attr| is_attr(attr, &["cfg", "doc"]))
+        .collect();
+    let cfgs: Vec<_> = input
+        .attrs
+        .iter()
+        .filter(|attr| is_attr(attr, &["cfg"]))
+        .collect();
+
+    let endpoint = expand_endpoint(input.clone(), &krate)?;
+
+    Ok(quote! {
+        #(#attrs)*
+        #[allow(non_camel_case_types)]
+        #vis struct #name;
+
+        #(#cfgs)*
+        impl #krate::router::Service for #name {
+            fn register(self, router: &mut #krate::router::Router) {
+                #endpoint
+                router.route(#path).#method(#name);
+            }
+        }
+    })
+}
+
+/// The names of the parameters in a route pattern, not including any wildcard.
+fn path_params(path: &syn::LitStr) -> syn::Result<Vec<String>> {
+    let value = path.value();
+
+    if !value.starts_with('/') {
+        return Err(syn::Error::new_spanned(path, "paths must start with `/`"));
+    }
+
+    let mut params: Vec<String> = Vec::new();
+
+    for segment in value.split('/') {
+        let name = match segment.strip_prefix(':') {
+            Some(name) => name,
+            None => continue,
+        };
+
+        if name.is_empty() {
+            return Err(syn::Error::new_spanned(
+                path,
+                "path parameters must have a name",
+            ));
+        }
+
+        if params.iter().any(|param| param == name) {
+            return Err(syn::Error::new_spanned(
+                path,
+                format!("duplicate path parameter `{}`", name),
+            ));
+        }
+
+        params.push(name.to_owned());
+    }
+
+    Ok(params)
+}
+
+/// Returns the `T` in an argument of type `Path<T>`.
+fn path_extractor(arg: &syn::FnArg) -> Option<&syn::Type> {
+    let ty = match arg {
+        syn::FnArg::Typed(arg) => &*arg.ty,
+        syn::FnArg::Receiver(_) => return None,
+    };
+
+    let segments = match ty {
+        syn::Type::Path(ty) => &ty.path.segments,
+        _ => return None,
+    };
+
+    let segment = segments.last()?;
+
+    // A qualified path must be to `extract::Path`, so that other crates' `Path`
+    // types aren't mistaken for it.
+    let qualifier = segments.iter().rev().nth(1);
+
+    if segment.ident != "Path" || qualifier.is_some_and(|q| q.ident != "extract") {
+        return None;
+    }
+
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Types that are deserialized from a single path parameter.
+const SCALARS: &[&str] = &[
+    "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32",
+    "u64", "u128", "usize", "String", "Uuid",
+];
+
+fn check_path_extractor(
+    input: &syn::ItemFn,
+    path: &syn::LitStr,
+    params: &[String],
+) -> syn::Result<()> {
+    let ty = match input.sig.inputs.iter().find_map(path_extractor) {
+        Some(ty) => ty,
+        None => return Ok(()),
+    };
+
+    let count_error = |expected: usize| {
+        syn::Error::new_spanned(
+            ty,
+            format!(
+                "expected {} path parameter{}, but `{}` has {}",
+                expected,
+                if expected == 1 { "" } else { "s" },
+                path.value(),
+                params.len()
+            ),
+        )
+    };
+
+    let ty_path = match ty {
+        syn::Type::Tuple(tuple) if tuple.elems.len() != params.len() => {
+            return Err(count_error(tuple.elems.len()));
+        }
+        syn::Type::Path(ty) if ty.qself.is_none() => &ty.path,
+        _ => return Ok(()),
+    };
+
+    // Maps take any number of parameters, and structs are declared outside of the
+    // endpoint where their fields can't be seen, so only scalars are left to check.
+    let is_scalar = ty_path
+        .segments
+        .last()
+        .is_some_and(|last| SCALARS.iter().any(|scalar| last.ident == scalar));
+
+    if is_scalar && params.len() != 1 {
+        return Err(count_error(1));
+    }
+
+    Ok(())
+}
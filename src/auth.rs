@@ -0,0 +1,294 @@
+use std::future::Future;
+
+use async_trait::async_trait;
+use headers::authorization::Credentials;
+use headers::Authorization;
+use hyper::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::StatusCode;
+
+use crate::{Handler, Next, Request, Response};
+
+pub use headers::authorization::{Basic, Bearer};
+
+/// Checks credentials, returning the principal they belong to if they are valid.
+///
+/// This is implemented for async functions and closures that take the credentials
+/// and return an `Option`.
+#[async_trait]
+pub trait Verifier<C>: Send + Sync + 'static {
+    type Principal: Send + Sync + 'static;
+
+    async fn verify(&self, credentials: C) -> Option<Self::Principal>;
+}
+
+#[async_trait]
+impl<C, F, Fut, P> Verifier<C> for F
+where
+    C: Send + 'static,
+    F: Fn(C) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Option<P>> + Send,
+    P: Send + Sync + 'static,
+{
+    type Principal = P;
+
+    async fn verify(&self, credentials: C) -> Option<P> {
+        self(credentials).await
+    }
+}
+
+/// Middleware that authenticates requests with
+/// [HTTP Basic authentication](https://datatracker.ietf.org/doc/html/rfc7617).
+///
+/// The principal returned by the verifier can be retrieved by later handlers with
+/// [`AuthRequestExt::principal`].
+pub struct BasicAuth<V> {
+    verifier: V,
+    realm: String,
+    optional: bool,
+}
+
+impl<V: Verifier<Basic>> BasicAuth<V> {
+    pub fn new(verifier: V) -> Self {
+        BasicAuth {
+            verifier,
+            realm: "atium".to_owned(),
+            optional: false,
+        }
+    }
+
+    /// Sets the realm sent in the `WWW-Authenticate` challenge.
+    pub fn with_realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    /// Lets requests without Basic credentials through anonymously, including those
+    /// with an `Authorization` header of another scheme. Requests with invalid
+    /// credentials, or a Basic header that can't be parsed, are still rejected.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+}
+
+/// Middleware that authenticates requests with
+/// [bearer tokens](https://datatracker.ietf.org/doc/html/rfc6750).
+///
+/// The principal returned by the verifier can be retrieved by later handlers with
+/// [`AuthRequestExt::principal`].
+pub struct BearerAuth<V> {
+    verifier: V,
+    realm: String,
+    optional: bool,
+}
+
+impl<V: Verifier<Bearer>> BearerAuth<V> {
+    pub fn new(verifier: V) -> Self {
+        BearerAuth {
+            verifier,
+            realm: "atium".to_owned(),
+            optional: false,
+        }
+    }
+
+    /// Sets the realm sent in the `WWW-Authenticate` challenge.
+    pub fn with_realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    /// Lets requests without a bearer token through anonymously, including those with
+    /// an `Authorization` header of another scheme. Requests with an invalid token, or
+    /// a Bearer header that can't be parsed, are still rejected.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+}
+
+struct Principal<P>(P);
+
+pub trait AuthRequestExt {
+    /// The principal the request was authenticated as, if any.
+    fn principal<P: Send + Sync + 'static>(&self) -> Option<&P>;
+}
+
+impl AuthRequestExt for Request {
+    fn principal<P: Send + Sync + 'static>(&self) -> Option<&P> {
+        self.ext::<Principal<P>>().map(|principal| &principal.0)
+    }
+}
+
+/// Stores the principal the request was authenticated as.
+pub(crate) fn set_principal<P: Send + Sync + 'static>(req: &mut Request, principal: P) {
+    req.set_ext(Principal(principal));
+}
+
+/// Quotes a value for use in a `WWW-Authenticate` challenge.
+pub(crate) fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Responds with `401 Unauthorized` and the given challenge.
+pub(crate) fn unauthorized(req: &mut Request, challenge: String) {
+    let mut res = Response::from(StatusCode::UNAUTHORIZED);
+
+    if let Ok(value) = HeaderValue::from_str(&challenge) {
+        res.headers_mut().insert(WWW_AUTHENTICATE, value);
+    }
+
+    req.set_res(res);
+}
+
+/// Whether the request's `Authorization` header uses the given scheme, whether or not
+/// its credentials can be parsed.
+pub(crate) fn has_scheme(req: &Request, scheme: &str) -> bool {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.as_bytes().split(|&b| b == b' ').next())
+        .is_some_and(|name| name.eq_ignore_ascii_case(scheme.as_bytes()))
+}
+
+/// Authenticates a request with the credentials of its `Authorization` header, if it
+/// has them. Returns the challenge to respond with if authentication fails.
+async fn authenticate<C, V>(
+    req: &mut Request,
+    verifier: &V,
+    challenge: String,
+    optional: bool,
+) -> Result<(), String>
+where
+    C: Credentials + Send + 'static,
+    V: Verifier<C>,
+{
+    let credentials = match req.header::<Authorization<C>>() {
+        Some(Authorization(credentials)) => credentials,
+        None if optional && !has_scheme(req, C::SCHEME) => return Ok(()),
+        None => return Err(challenge),
+    };
+
+    match verifier.verify(credentials).await {
+        Some(principal) => {
+            set_principal(req, principal);
+            Ok(())
+        }
+        None => Err(challenge),
+    }
+}
+
+#[async_trait]
+impl<V: Verifier<Basic>> Handler for BasicAuth<V> {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let challenge = format!("Basic realm={}, charset=\"UTF-8\"", quote(&self.realm));
+
+        match authenticate(&mut req, &self.verifier, challenge, self.optional).await {
+            Ok(()) => next.run(req).await,
+            Err(challenge) => {
+                unauthorized(&mut req, challenge);
+                req
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<V: Verifier<Bearer>> Handler for BearerAuth<V> {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        // Clients that sent a token should be told that it was the problem.
+        let challenge = match req.header::<Authorization<Bearer>>() {
+            Some(_) => format!(
+                "Bearer realm={}, error=\"invalid_token\"",
+                quote(&self.realm)
+            ),
+            None if req.headers().contains_key(AUTHORIZATION) => format!(
+                "Bearer realm={}, error=\"invalid_request\"",
+                quote(&self.realm)
+            ),
+            None => format!("Bearer realm={}", quote(&self.realm)),
+        };
+
+        match authenticate(&mut req, &self.verifier, challenge, self.optional).await {
+            Ok(()) => next.run(req).await,
+            Err(challenge) => {
+                unauthorized(&mut req, challenge);
+                req
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    async fn status(handler: impl Handler, authorization: Option<&str>) -> StatusCode {
+        let mut req = hyper::Request::builder();
+        if let Some(value) = authorization {
+            req = req.header(AUTHORIZATION, value);
+        }
+        let req = Request::new(req.body(Body::empty()).unwrap(), None);
+
+        let endpoint = |mut req: Request| async move {
+            req.set_res(StatusCode::OK);
+            req
+        };
+
+        let req = (handler, endpoint)
+            .run(req, &NextFn(|req| async move { req }))
+            .await;
+        req.res().unwrap().status()
+    }
+
+    fn basic() -> BasicAuth<impl Verifier<Basic, Principal = ()>> {
+        BasicAuth::new(|_: Basic| async { Some(()) }).optional()
+    }
+
+    fn bearer() -> BearerAuth<impl Verifier<Bearer, Principal = ()>> {
+        BearerAuth::new(|_: Bearer| async { Some(()) }).optional()
+    }
+
+    #[tokio::test]
+    async fn optional_allows_missing_header() {
+        assert_eq!(status(basic(), None).await, StatusCode::OK);
+        assert_eq!(status(bearer(), None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn optional_rejects_malformed_header() {
+        assert_eq!(
+            status(basic(), Some("Basic !!!")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(bearer(), Some("Bearer")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(bearer(), Some("bearer ")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn optional_ignores_other_schemes() {
+        assert_eq!(status(bearer(), Some("Basic dTpw")).await, StatusCode::OK);
+        assert_eq!(status(basic(), Some("Bearer token")).await, StatusCode::OK);
+        assert_eq!(status(basic(), Some("Basically")).await, StatusCode::OK);
+
+        // Without `optional`, they are still rejected.
+        let bearer = BearerAuth::new(|_: Bearer| async { Some(()) });
+        assert_eq!(
+            status(bearer, Some("Basic dTpw")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn optional_accepts_valid_header() {
+        assert_eq!(status(basic(), Some("Basic dTpw")).await, StatusCode::OK);
+        assert_eq!(status(bearer(), Some("Bearer token")).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,165 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+
+use async_trait::async_trait;
+use futures::FutureExt;
+use hyper::StatusCode;
+
+use crate::handler::PanickedHandler;
+use crate::{Handler, Next, Request, Response};
+
+type ResponseFn = Box<dyn Fn(&str) -> Response + Send + Sync>;
+
+/// Middleware that turns panics in later handlers into `500 Internal Server Error`
+/// responses, rather than dropping the connection.
+///
+/// The panic message and the name of the handler that panicked are logged. The
+/// response has no body by default, so that the panic message isn't leaked to
+/// clients, but this can be changed with [`CatchPanic::with_response`].
+///
+/// This should be placed inside any [`Logger`] so that panicking requests are
+/// logged. As with [`Timeout`], the response is generated from a copy of the request
+/// as it was before it was passed on, so extensions set by later handlers are lost.
+///
+/// [`Logger`]: crate::logger::Logger
+/// [`Timeout`]: crate::timeout::Timeout
+#[derive(Default)]
+pub struct CatchPanic {
+    response: Option<ResponseFn>,
+}
+
+impl CatchPanic {
+    pub fn new() -> Self {
+        CatchPanic::default()
+    }
+
+    /// Sets a function that creates the response from the panic message.
+    pub fn with_response(
+        mut self,
+        response: impl Fn(&str) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.response = Some(Box::new(response));
+        self
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+#[async_trait]
+impl Handler for CatchPanic {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        // Share the slot of any outer `CatchPanic`, in case a handler between the two
+        // panics once this one has returned.
+        let panicked = match req.ext::<PanickedHandler>() {
+            Some(panicked) => panicked.clone(),
+            None => {
+                let panicked = PanickedHandler::default();
+                req.set_cloneable_ext(panicked.clone());
+                panicked
+            }
+        };
+
+        let head = req.clone_head();
+
+        let panic = match AssertUnwindSafe(next.run(req)).catch_unwind().await {
+            Ok(req) => return req,
+            Err(panic) => panic,
+        };
+
+        let handler = panicked.take();
+        let message = panic_message(panic.as_ref());
+
+        log::error!(
+            "handler {} panicked while handling {} {}: {}",
+            handler.as_deref().unwrap_or("<unknown>"),
+            head.method(),
+            head.uri().path(),
+            message
+        );
+
+        let res = match &self.response {
+            Some(response) => response(message),
+            None => Response::from(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+        let mut req = head;
+        req.set_res(res);
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    #[tokio::test]
+    async fn responds_to_panics() {
+        let endpoint = |req: Request| async move {
+            if req.uri().path() == "/panic" {
+                panic!("oops");
+            }
+            req
+        };
+
+        let catch_panic = CatchPanic::new().with_response(|message| {
+            Response::from(message.to_owned()).with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        });
+
+        let req = hyper::Request::get("/panic").body(Body::empty()).unwrap();
+        let mut req = (catch_panic, endpoint)
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await;
+
+        let mut res = req.take_res().unwrap();
+        let body = hyper::body::to_bytes(res.take_body()).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(&body[..], b"oops");
+        assert_eq!(req.uri().path(), "/panic");
+    }
+
+    #[tokio::test]
+    async fn panicked_requests_keep_earlier_extensions() {
+        use std::sync::{Arc, Mutex};
+
+        use crate::logger::{LogFormat, LogRecord, Logger};
+        use crate::request_id::RequestId;
+
+        let lines = Arc::new(Mutex::new(vec![]));
+        let sink = {
+            let lines = lines.clone();
+            move |_: &LogRecord, line: &str| lines.lock().unwrap().push(line.to_owned())
+        };
+
+        let logger = Logger::new()
+            .with_format(LogFormat::custom("{status} {request_id} {user_agent}").unwrap())
+            .with_sink(sink);
+        let request_id = RequestId::new().with_generator(|| "abc".to_owned());
+        let endpoint = |_: Request| async move { panic!("oops") };
+
+        let req = hyper::Request::get("/")
+            .header(hyper::header::USER_AGENT, "test")
+            .body(Body::empty())
+            .unwrap();
+        let req = (logger, (request_id, (CatchPanic::new(), endpoint)))
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await;
+
+        assert_eq!(
+            req.res().unwrap().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(*lines.lock().unwrap(), ["500 abc test"]);
+    }
+}
@@ -0,0 +1,689 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_compression::tokio::bufread::{
+    BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder, ZlibDecoder, ZlibEncoder, ZstdDecoder,
+    ZstdEncoder,
+};
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use headers::{CacheControl, ContentType};
+use hyper::body::HttpBody;
+use hyper::header::{
+    HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, ETAG,
+};
+use hyper::{Body, Method, StatusCode};
+use tokio::io::{AsyncRead, BufReader};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::encoding::{AcceptEncoding, Encoding, UnknownEncoding};
+use crate::{Handler, Next, Request, Response};
+
+/// How hard to try to compress responses, trading speed for size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Level {
+    Fastest,
+    Best,
+    #[default]
+    Default,
+    /// A level specific to the encoding used, e.g. 0-11 for brotli. Values out of
+    /// range for an encoding are clamped to it.
+    Precise(i32),
+}
+
+impl From<Level> for async_compression::Level {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Fastest => async_compression::Level::Fastest,
+            Level::Best => async_compression::Level::Best,
+            Level::Default => async_compression::Level::Default,
+            Level::Precise(quality) => async_compression::Level::Precise(quality),
+        }
+    }
+}
+
+/// Middleware that compresses response bodies according to the client's
+/// `Accept-Encoding` header.
+pub struct Compression {
+    encodings: Vec<Encoding>,
+    min_size: u64,
+    level: Level,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            encodings: vec![
+                Encoding::Brotli,
+                Encoding::Zstd,
+                Encoding::Gzip,
+                Encoding::Deflate,
+            ],
+            min_size: 1024,
+            level: Level::Default,
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression::default()
+    }
+
+    /// Sets the encodings that may be used, in order of preference when the client
+    /// accepts several of them equally.
+    pub fn with_encodings(mut self, encodings: impl IntoIterator<Item = Encoding>) -> Self {
+        self.encodings = encodings
+            .into_iter()
+            .filter(|encoding| *encoding != Encoding::Identity)
+            .collect();
+        self
+    }
+
+    /// Sets the minimum body size in bytes for a response to be compressed. Bodies of
+    /// unknown length are always compressed.
+    pub fn with_min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    fn should_compress(&self, res: &Response, is_head: bool) -> bool {
+        let status = res.status();
+
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            return false;
+        }
+
+        if res.headers().contains_key(CONTENT_ENCODING) {
+            return false;
+        }
+
+        let no_transform = res
+            .header::<CacheControl>()
+            .is_some_and(|cache_control| cache_control.no_transform());
+
+        if no_transform {
+            return false;
+        }
+
+        // The body of a response to a HEAD request is empty, so only the header says
+        // how large the body of a GET would be.
+        let length = if is_head {
+            content_length_header(res)
+        } else {
+            content_length(res)
+        };
+
+        match length {
+            Some(length) => length >= self.min_size,
+            None => true,
+        }
+    }
+
+    fn encode(&self, body: Body, encoding: Encoding) -> Body {
+        let body = TryStreamExt::map_err(body, io::Error::other);
+        let reader = StreamReader::new(body);
+        let level = self.level.into();
+
+        fn wrap(reader: impl AsyncRead + Send + 'static) -> Body {
+            Body::wrap_stream(ReaderStream::new(reader))
+        }
+
+        match encoding {
+            Encoding::Gzip => wrap(GzipEncoder::with_quality(reader, level)),
+            // The HTTP deflate coding is actually the zlib format.
+            Encoding::Deflate => wrap(ZlibEncoder::with_quality(reader, level)),
+            Encoding::Brotli => wrap(BrotliEncoder::with_quality(reader, level)),
+            Encoding::Zstd => wrap(ZstdEncoder::with_quality(reader, level)),
+            Encoding::Identity => wrap(reader),
+        }
+    }
+}
+
+fn content_length(res: &Response) -> Option<u64> {
+    content_length_header(res).or_else(|| res.body().size_hint().exact())
+}
+
+fn content_length_header(res: &Response) -> Option<u64> {
+    res.headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Returns whether a response of the given content type is worth compressing.
+fn is_compressible(res: &Response) -> bool {
+    let mime: mime::Mime = match res.header::<ContentType>() {
+        Some(content_type) => content_type.into(),
+        None => return false,
+    };
+
+    match (mime.type_(), mime.subtype()) {
+        (mime::TEXT, _) => true,
+        (mime::IMAGE, mime::SVG) => true,
+        (mime::APPLICATION, subtype) => {
+            matches!(
+                subtype.as_str(),
+                "json" | "javascript" | "ecmascript" | "xml" | "wasm" | "x-www-form-urlencoded"
+            ) || matches!(mime.suffix(), Some(mime::JSON) | Some(mime::XML))
+        }
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl Handler for Compression {
+    async fn run(&self, req: Request, next: &dyn Next) -> Request {
+        let accept = AcceptEncoding::from_headers(req.headers());
+        let is_head = req.method() == Method::HEAD;
+
+        let mut req = next.run(req).await;
+
+        let res = match req.res_mut() {
+            Some(res) if is_compressible(res) => res,
+            _ => return req,
+        };
+
+        res.add_vary(ACCEPT_ENCODING);
+
+        if !self.should_compress(res, is_head) {
+            return req;
+        }
+
+        let encoding = accept
+            .map(|accept| accept.preferred(&self.encodings))
+            .and_then(|preferred| preferred.first().copied());
+
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => return req,
+        };
+
+        // HEAD responses get the same headers as a GET would, but have no body to
+        // encode.
+        if !is_head {
+            let body = self.encode(res.take_body(), encoding);
+            res.set_body(body);
+        }
+
+        let headers = res.headers_mut();
+
+        headers.remove(CONTENT_LENGTH);
+        headers.remove(ACCEPT_RANGES);
+        headers.insert(CONTENT_ENCODING, encoding.to_header_value());
+
+        // The compressed body is no longer byte-for-byte identical to the original
+        // representation, so any strong validator must be weakened.
+        if let Some(etag) = headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+            if !etag.starts_with("W/") {
+                if let Ok(weak) = HeaderValue::from_str(&format!("W/{}", etag)) {
+                    headers.insert(ETAG, weak);
+                }
+            }
+        }
+
+        req
+    }
+}
+
+/// The default limit on the size of a decoded request body, so that small
+/// compressed bodies can't expand to fill memory.
+const DEFAULT_MAX_DECODED_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Middleware that transparently decodes request bodies sent with a
+/// `Content-Encoding`, before they are read by later handlers.
+///
+/// Requests using an unsupported encoding are rejected with
+/// `415 Unsupported Media Type`. Decoded bodies are limited to 4 MiB by default.
+pub struct Decompression {
+    max_size: Option<u64>,
+}
+
+impl Default for Decompression {
+    fn default() -> Self {
+        Decompression {
+            max_size: Some(DEFAULT_MAX_DECODED_SIZE),
+        }
+    }
+}
+
+/// Marker set on the request when a decoded body exceeded the configured limit.
+#[derive(Clone)]
+struct LimitExceeded(Arc<AtomicBool>);
+
+impl Decompression {
+    pub fn new() -> Self {
+        Decompression::default()
+    }
+
+    /// Sets the maximum size in bytes of a decoded body. Reading past the limit
+    /// fails, and the request is answered with `413 Payload Too Large`.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Removes the limit on the size of decoded bodies, e.g. when later handlers
+    /// stream them somewhere rather than buffering them.
+    pub fn without_max_size(mut self) -> Self {
+        self.max_size = None;
+        self
+    }
+
+    fn decode(&self, body: Body, encodings: &[Encoding], exceeded: LimitExceeded) -> Body {
+        let body = TryStreamExt::map_err(body, io::Error::other);
+        let mut reader: Pin<Box<dyn AsyncRead + Send>> = Box::pin(StreamReader::new(body));
+
+        // Codings are listed in the order they were applied, so undo them in reverse.
+        for encoding in encodings.iter().rev() {
+            let buffered = BufReader::new(reader);
+            reader = match encoding {
+                Encoding::Gzip => Box::pin(GzipDecoder::new(buffered)),
+                Encoding::Deflate => Box::pin(ZlibDecoder::new(buffered)),
+                Encoding::Brotli => Box::pin(BrotliDecoder::new(buffered)),
+                Encoding::Zstd => Box::pin(ZstdDecoder::new(buffered)),
+                Encoding::Identity => Box::pin(buffered),
+            };
+        }
+
+        let max_size = self.max_size.unwrap_or(u64::MAX);
+        let mut total = 0u64;
+
+        let stream = ReaderStream::new(reader).map(move |chunk| {
+            let chunk = chunk?;
+            total += chunk.len() as u64;
+
+            if total > max_size {
+                exceeded.0.store(true, Ordering::Relaxed);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "decoded body exceeds size limit",
+                ));
+            }
+
+            Ok(chunk)
+        });
+
+        Body::wrap_stream(stream)
+    }
+}
+
+fn content_encodings(req: &Request) -> Result<Vec<Encoding>, UnknownEncoding> {
+    req.headers()
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        .map(|value| value.to_str().map_err(|_| UnknownEncoding))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .map(str::parse)
+        .filter(|encoding| !matches!(encoding, Ok(Encoding::Identity)))
+        .collect()
+}
+
+#[async_trait]
+impl Handler for Decompression {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let encodings = match content_encodings(&req) {
+            Ok(encodings) => encodings,
+            Err(_) => {
+                let supported = [
+                    Encoding::Gzip,
+                    Encoding::Deflate,
+                    Encoding::Brotli,
+                    Encoding::Zstd,
+                ]
+                .iter()
+                .map(Encoding::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+
+                let mut res = Response::from(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+                res.headers_mut()
+                    .insert(ACCEPT_ENCODING, HeaderValue::from_str(&supported).unwrap());
+                req.set_res(res);
+                return req;
+            }
+        };
+
+        if encodings.is_empty() {
+            return next.run(req).await;
+        }
+
+        let exceeded = LimitExceeded(Arc::new(AtomicBool::new(false)));
+        let body = self.decode(req.body(), &encodings, exceeded.clone());
+
+        let headers = req.headers_mut();
+        headers.remove(CONTENT_ENCODING);
+        headers.remove(CONTENT_LENGTH);
+
+        req.set_body(body);
+
+        let mut req = next.run(req).await;
+
+        if exceeded.0.load(Ordering::Relaxed) {
+            req.set_res(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    async fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![];
+        GzipEncoder::new(data)
+            .read_to_end(&mut encoded)
+            .await
+            .unwrap();
+        encoded
+    }
+
+    /// Posts a gzipped body through `Decompression` to an endpoint that reads it as
+    /// JSON, returning the response status.
+    async fn post_json(decompression: Decompression, body: Vec<u8>) -> StatusCode {
+        let req = hyper::Request::post("/")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(Body::from(body))
+            .unwrap();
+
+        let endpoint = |mut req: Request| async move {
+            match req.body_json::<Value>().await {
+                Ok(_) => req.set_res(StatusCode::OK),
+                Err(_) => req.set_res(StatusCode::BAD_REQUEST),
+            };
+            req
+        };
+
+        let req = (decompression, endpoint)
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await;
+
+        req.res().unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn decodes_json_body() {
+        let body = gzip(br#"{"hello": "world"}"#).await;
+        let status = post_json(Decompression::new(), body).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn truncated_body_is_an_error() {
+        let mut body = gzip(br#"{"hello": "world"}"#).await;
+        body.truncate(body.len() - 10);
+
+        let status = post_json(Decompression::new(), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_rejected() {
+        let json = format!(r#"{{"data": "{}"}}"#, "a".repeat(10_000));
+        let body = gzip(json.as_bytes()).await;
+
+        let status = post_json(Decompression::new().with_max_size(1000), body).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    /// Posts `size` bytes of gzipped data through `Decompression` to an endpoint that
+    /// reads the whole body, ignoring errors, and responds with `200 OK`.
+    async fn post_drained(decompression: Decompression, size: usize) -> (StatusCode, usize) {
+        let body = gzip(&vec![b'a'; size]).await;
+        let req = hyper::Request::post("/")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(Body::from(body))
+            .unwrap();
+
+        let read = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let endpoint = {
+            let read = read.clone();
+            move |mut req: Request| {
+                let read = read.clone();
+                async move {
+                    let mut body = req.body();
+                    while let Some(chunk) = body.next().await {
+                        if let Ok(chunk) = chunk {
+                            read.fetch_add(chunk.len(), Ordering::Relaxed);
+                        }
+                    }
+
+                    req.set_res(StatusCode::OK);
+                    req
+                }
+            }
+        };
+
+        let req = (decompression, endpoint)
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await;
+
+        (req.res().unwrap().status(), read.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test]
+    async fn drained_oversized_body_is_rejected() {
+        let (status, read) = post_drained(Decompression::new().with_max_size(1000), 10_000).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(read <= 1000);
+    }
+
+    #[tokio::test]
+    async fn limits_decoded_size_by_default() {
+        let size = DEFAULT_MAX_DECODED_SIZE as usize;
+
+        let (status, _) = post_drained(Decompression::new(), size).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = post_drained(Decompression::new(), size + 1).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (status, read) = post_drained(Decompression::new().without_max_size(), size + 1).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(read, size + 1);
+    }
+
+    /// Sends a request with the given method and `Accept-Encoding` through
+    /// `Compression` to an endpoint that responds with `res`, without its body for
+    /// HEAD requests.
+    async fn compress_with(
+        method: Method,
+        accept: Option<&str>,
+        res: impl Fn() -> Response + Send + Sync + 'static,
+    ) -> Response {
+        let mut req = hyper::Request::builder().method(method);
+        if let Some(accept) = accept {
+            req = req.header(ACCEPT_ENCODING, accept);
+        }
+        let req = Request::new(req.body(Body::empty()).unwrap(), None);
+
+        let endpoint = move |mut req: Request| {
+            let mut res = res();
+            async move {
+                if req.method() == Method::HEAD {
+                    res.set_body(Body::empty());
+                }
+
+                req.set_res(res);
+                req
+            }
+        };
+
+        let mut req = (Compression::new(), endpoint)
+            .run(req, &NextFn(|req| async move { req }))
+            .await;
+
+        req.take_res().unwrap()
+    }
+
+    /// 2 KiB of text, with a strong ETag.
+    fn text() -> Response {
+        let body = "a".repeat(2048);
+        let mut res = Response::ok().with_header(ContentType::text());
+        res.headers_mut().insert(CONTENT_LENGTH, body.len().into());
+        res.headers_mut()
+            .insert(ETAG, HeaderValue::from_static("\"abc\""));
+        res.set_body(body);
+        res
+    }
+
+    async fn compress(method: Method) -> Response {
+        compress_with(method, Some("gzip"), text).await
+    }
+
+    async fn decode(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut decoded = vec![];
+        match encoding {
+            "gzip" => GzipDecoder::new(body).read_to_end(&mut decoded).await,
+            "br" => BrotliDecoder::new(body).read_to_end(&mut decoded).await,
+            _ => panic!("unexpected encoding {}", encoding),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[tokio::test]
+    async fn encodes_with_preferred_encoding() {
+        let cases = [
+            ("gzip", Some("gzip")),
+            ("gzip;q=0.5, br", Some("br")),
+            ("br;q=0.1, gzip;q=0.9", Some("gzip")),
+            // The server's preference decides between equally acceptable encodings.
+            ("gzip, br", Some("br")),
+            ("*", Some("br")),
+            ("br;q=0, gzip;q=0", None),
+            ("identity", None),
+        ];
+
+        for (accept, expected) in &cases {
+            let mut res = compress_with(Method::GET, Some(accept), text).await;
+            let encoding = res
+                .headers()
+                .get(CONTENT_ENCODING)
+                .map(|value| value.to_str().unwrap().to_owned());
+            assert_eq!(
+                encoding.as_deref(),
+                *expected,
+                "Accept-Encoding: {}",
+                accept
+            );
+
+            let body = hyper::body::to_bytes(res.take_body()).await.unwrap();
+            let body = match expected {
+                Some(encoding) => decode(encoding, &body).await,
+                None => body.to_vec(),
+            };
+            assert_eq!(body, "a".repeat(2048).as_bytes());
+        }
+
+        let res = compress_with(Method::GET, None, text).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn skips_unsuitable_responses() {
+        fn with_status(status: StatusCode) -> impl Fn() -> Response {
+            move || text().with_status(status)
+        }
+
+        let small = || Response::from("small".to_owned()).with_header(ContentType::text());
+        let png = || text().with_header(ContentType::png());
+        let no_transform = || text().with_header(CacheControl::new().with_no_transform());
+        let encoded = || {
+            let mut res = text();
+            res.headers_mut()
+                .insert(CONTENT_ENCODING, HeaderValue::from_static("zstd"));
+            res
+        };
+
+        let mut responses = vec![
+            compress_with(Method::GET, Some("gzip"), small).await,
+            compress_with(Method::GET, Some("gzip"), png).await,
+            compress_with(Method::GET, Some("gzip"), no_transform).await,
+            compress_with(
+                Method::GET,
+                Some("gzip"),
+                with_status(StatusCode::PARTIAL_CONTENT),
+            )
+            .await,
+            compress_with(
+                Method::GET,
+                Some("gzip"),
+                with_status(StatusCode::NOT_MODIFIED),
+            )
+            .await,
+        ];
+
+        for res in &responses {
+            assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        }
+
+        // Compressible responses still vary, as larger ones would be compressed.
+        assert_eq!(
+            responses[0].headers()[hyper::header::VARY],
+            "accept-encoding"
+        );
+        assert!(responses[1].headers().get(hyper::header::VARY).is_none());
+
+        let body = hyper::body::to_bytes(responses[2].take_body())
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 2048);
+
+        let res = compress_with(Method::GET, Some("gzip"), encoded).await;
+        assert_eq!(res.headers()[CONTENT_ENCODING], "zstd");
+        assert_eq!(res.headers()[ETAG], "\"abc\"");
+    }
+
+    #[tokio::test]
+    async fn weak_etags_are_kept() {
+        let weak = || {
+            let mut res = text();
+            res.headers_mut()
+                .insert(ETAG, HeaderValue::from_static("W/\"abc\""));
+            res
+        };
+
+        let res = compress_with(Method::GET, Some("gzip"), weak).await;
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(res.headers()[ETAG], "W/\"abc\"");
+    }
+
+    #[tokio::test]
+    async fn head_is_negotiated_like_get() {
+        let get = compress(Method::GET).await;
+        let mut head = compress(Method::HEAD).await;
+
+        for res in [&get, &head] {
+            assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+            assert_eq!(res.headers()[hyper::header::VARY], "accept-encoding");
+            assert_eq!(res.headers()[ETAG], "W/\"abc\"");
+            assert!(res.headers().get(CONTENT_LENGTH).is_none());
+        }
+
+        let body = hyper::body::to_bytes(head.take_body()).await.unwrap();
+        assert!(body.is_empty());
+    }
+}
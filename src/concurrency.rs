@@ -0,0 +1,275 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use hyper::StatusCode;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::metrics::{Counter, Gauge, Metrics};
+use crate::{Handler, Next, Request, Response};
+
+/// Middleware that limits the number of requests handled at once.
+///
+/// Requests over the limit wait in a queue for up to the queue timeout. When the
+/// queue is full or the timeout passes, the request is shed with
+/// `503 Service Unavailable` and a `Retry-After` header.
+///
+/// Each `ConcurrencyLimit` has its own limit, so it can be applied globally or to
+/// individual routes.
+pub struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    max_waiting: usize,
+    timeout: Duration,
+    retry_after: Duration,
+    metrics: Option<LimitMetrics>,
+}
+
+struct LimitMetrics {
+    in_flight: Gauge,
+    queue_depth: Gauge,
+    rejected: Counter,
+}
+
+impl ConcurrencyLimit {
+    /// Allows at most `max_in_flight` requests to be handled at once. By default,
+    /// there is no queue and excess requests are shed immediately.
+    pub fn new(max_in_flight: usize) -> Self {
+        ConcurrencyLimit {
+            semaphore: Arc::new(Semaphore::new(max_in_flight)),
+            waiting: AtomicUsize::new(0),
+            max_waiting: 0,
+            timeout: Duration::from_secs(30),
+            retry_after: Duration::from_secs(1),
+            metrics: None,
+        }
+    }
+
+    /// Lets up to `max_waiting` requests wait for a slot.
+    pub fn with_queue(mut self, max_waiting: usize) -> Self {
+        self.max_waiting = max_waiting;
+        self
+    }
+
+    /// Sets how long requests wait in the queue before being shed. Defaults to 30
+    /// seconds.
+    pub fn with_queue_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the `Retry-After` header sent with shed requests. Defaults to 1 second.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Exports the number of requests in flight and waiting, and the number of shed
+    /// requests, as `{name}_in_flight`, `{name}_queue_depth` and
+    /// `{name}_rejected_total`.
+    pub fn with_metrics(mut self, metrics: &Metrics, name: &str) -> Self {
+        self.metrics = Some(LimitMetrics {
+            in_flight: metrics.gauge(
+                format!("{}_in_flight", name),
+                "Number of requests being handled under the concurrency limit.",
+            ),
+            queue_depth: metrics.gauge(
+                format!("{}_queue_depth", name),
+                "Number of requests waiting for the concurrency limit.",
+            ),
+            rejected: metrics.counter(
+                format!("{}_rejected_total", name),
+                "Number of requests shed by the concurrency limit.",
+            ),
+        });
+        self
+    }
+
+    async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.max_waiting {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        let _waiting = Waiting::new(self);
+        let acquire = self.semaphore.clone().acquire_owned();
+
+        match tokio::time::timeout(self.timeout, acquire).await {
+            Ok(Ok(permit)) => Some(permit),
+            _ => None,
+        }
+    }
+
+    fn shed(&self, req: &mut Request) {
+        if let Some(metrics) = &self.metrics {
+            metrics.rejected.inc();
+        }
+
+        let mut res = Response::from(StatusCode::SERVICE_UNAVAILABLE);
+        res.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(self.retry_after.as_secs()));
+        req.set_res(res);
+    }
+}
+
+/// Tracks a request in the queue, removing it when dropped so that the count stays
+/// accurate if the request is cancelled while waiting.
+struct Waiting<'a>(&'a ConcurrencyLimit);
+
+impl<'a> Waiting<'a> {
+    fn new(limit: &'a ConcurrencyLimit) -> Self {
+        if let Some(metrics) = &limit.metrics {
+            metrics.queue_depth.inc();
+        }
+
+        Waiting(limit)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.waiting.fetch_sub(1, Ordering::SeqCst);
+
+        if let Some(metrics) = &self.0.metrics {
+            metrics.queue_depth.dec();
+        }
+    }
+}
+
+/// Tracks a request being handled, holding its slot until dropped.
+struct InFlight<'a> {
+    limit: &'a ConcurrencyLimit,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<'a> InFlight<'a> {
+    fn new(limit: &'a ConcurrencyLimit, permit: OwnedSemaphorePermit) -> Self {
+        if let Some(metrics) = &limit.metrics {
+            metrics.in_flight.inc();
+        }
+
+        InFlight {
+            limit,
+            _permit: permit,
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.limit.metrics {
+            metrics.in_flight.dec();
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for ConcurrencyLimit {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let permit = match self.acquire().await {
+            Some(permit) => permit,
+            None => {
+                self.shed(&mut req);
+                return req;
+            }
+        };
+
+        let _in_flight = InFlight::new(self, permit);
+        next.run(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    /// Sends a request through `limit` to an endpoint that takes a second to respond,
+    /// returning the response status, its `Retry-After` header and when it finished.
+    async fn request(limit: &ConcurrencyLimit) -> (StatusCode, Option<u64>, Duration) {
+        let start = Instant::now();
+        let req = Request::new(hyper::Request::new(Body::empty()), None);
+
+        let endpoint = NextFn(|mut req: Request| async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            req.set_res(StatusCode::OK);
+            req
+        });
+
+        let req = limit.run(req, &endpoint).await;
+        let res = req.res().unwrap();
+        let retry_after = res
+            .headers()
+            .get(RETRY_AFTER)
+            .map(|value| value.to_str().unwrap().parse().unwrap());
+
+        (res.status(), retry_after, start.elapsed())
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queues_then_sheds() {
+        let limit = ConcurrencyLimit::new(2)
+            .with_queue(1)
+            .with_retry_after(secs(10));
+
+        let (first, second, queued, shed) = futures::join!(
+            request(&limit),
+            request(&limit),
+            request(&limit),
+            request(&limit)
+        );
+
+        assert_eq!(first, (StatusCode::OK, None, secs(1)));
+        assert_eq!(second, (StatusCode::OK, None, secs(1)));
+        // The queued request waits for a permit to be released.
+        assert_eq!(queued, (StatusCode::OK, None, secs(2)));
+        // The queue is full, so the last request is shed straight away.
+        assert_eq!(shed, (StatusCode::SERVICE_UNAVAILABLE, Some(10), secs(0)));
+
+        assert_eq!(limit.waiting.load(Ordering::SeqCst), 0);
+        assert_eq!(limit.semaphore.available_permits(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sheds_after_queue_timeout() {
+        let limit = ConcurrencyLimit::new(1)
+            .with_queue(1)
+            .with_queue_timeout(Duration::from_millis(500));
+
+        let (first, timed_out) = futures::join!(request(&limit), request(&limit));
+
+        assert_eq!(first, (StatusCode::OK, None, secs(1)));
+        assert_eq!(
+            timed_out,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Some(1),
+                Duration::from_millis(500)
+            )
+        );
+        assert_eq!(limit.waiting.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sheds_immediately_without_queue() {
+        let limit = ConcurrencyLimit::new(1);
+
+        let (first, shed) = futures::join!(request(&limit), request(&limit));
+
+        assert_eq!(first.0, StatusCode::OK);
+        assert_eq!(shed, (StatusCode::SERVICE_UNAVAILABLE, Some(1), secs(0)));
+    }
+}
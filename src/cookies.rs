@@ -0,0 +1,143 @@
+use async_trait::async_trait;
+use hyper::header::{HeaderValue, COOKIE, SET_COOKIE};
+
+use crate::{Handler, Next, Request};
+
+pub use cookie::{
+    time, Cookie, CookieBuilder, CookieJar, Expiration, Key, PrivateJar, SameSite, SignedJar,
+};
+
+/// Middleware that writes changes made to the request's [`CookieJar`] back to the
+/// response as `Set-Cookie` headers.
+///
+/// Handlers get at the jar with [`CookieRequestExt::cookies`]. Cookies added to the
+/// jar are sent with all of their attributes, and removed cookies are sent expired
+/// so that the client deletes them.
+///
+/// With a [`Key`] configured, signed and encrypted cookies are available through
+/// [`CookieRequestExt::signed_cookies`] and [`CookieRequestExt::private_cookies`].
+#[derive(Default)]
+pub struct Cookies {
+    key: Option<Key>,
+}
+
+impl Cookies {
+    pub fn new() -> Self {
+        Cookies::default()
+    }
+
+    /// Sets the key used to sign and encrypt cookies.
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.key = Some(key);
+        self
+    }
+}
+
+#[derive(Clone)]
+struct Jar {
+    jar: CookieJar,
+    key: Option<Key>,
+    /// Whether the [`Cookies`] middleware will write the jar's changes.
+    managed: bool,
+}
+
+impl Jar {
+    fn parse(req: &Request, key: Option<Key>, managed: bool) -> Self {
+        let mut jar = CookieJar::new();
+
+        let cookies = req
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(Cookie::split_parse_encoded)
+            .filter_map(Result::ok);
+
+        for cookie in cookies {
+            jar.add_original(cookie.into_owned());
+        }
+
+        Jar { jar, key, managed }
+    }
+}
+
+pub trait CookieRequestExt {
+    /// The cookies sent with the request, along with any changes made by handlers.
+    ///
+    /// Changes are only sent to the client if the [`Cookies`] middleware is used, or
+    /// if they're made by a middleware that writes its own cookies, like `Session`.
+    fn cookies(&mut self) -> &mut CookieJar;
+
+    /// A view of the jar in which cookies are signed, so they can be read but not
+    /// tampered with by the client. Returns `None` if no key is configured.
+    fn signed_cookies(&mut self) -> Option<SignedJar<&mut CookieJar>>;
+
+    /// A view of the jar in which cookies are encrypted, so the client can neither
+    /// read nor tamper with them. Returns `None` if no key is configured.
+    fn private_cookies(&mut self) -> Option<PrivateJar<&mut CookieJar>>;
+}
+
+impl CookieRequestExt for Request {
+    fn cookies(&mut self) -> &mut CookieJar {
+        &mut jar(self).jar
+    }
+
+    fn signed_cookies(&mut self) -> Option<SignedJar<&mut CookieJar>> {
+        let Jar { jar, key, .. } = jar(self);
+        key.as_ref().map(move |key| jar.signed_mut(key))
+    }
+
+    fn private_cookies(&mut self) -> Option<PrivateJar<&mut CookieJar>> {
+        let Jar { jar, key, .. } = jar(self);
+        key.as_ref().map(move |key| jar.private_mut(key))
+    }
+}
+
+/// Returns the request's jar, parsing the `Cookie` header the first time it's needed.
+fn jar(req: &mut Request) -> &mut Jar {
+    if req.ext::<Jar>().is_none() {
+        let jar = Jar::parse(req, None, false);
+        req.set_cloneable_ext(jar);
+    }
+
+    req.ext_mut::<Jar>().unwrap()
+}
+
+/// Writes the jar's changes to the response, for middleware that set cookies and
+/// may be used without [`Cookies`]. Does nothing if [`Cookies`] will write them.
+pub(crate) fn write_cookies(req: &mut Request) {
+    if let Some(mut jar) = req.take_ext::<Jar>() {
+        if !jar.managed {
+            write_delta(req, &jar.jar);
+            jar.jar.reset_delta();
+        }
+
+        req.set_cloneable_ext(jar);
+    }
+}
+
+fn write_delta(req: &mut Request, jar: &CookieJar) {
+    if let Some(res) = req.res_mut() {
+        for cookie in jar.delta() {
+            if let Ok(value) = HeaderValue::from_str(&cookie.encoded().to_string()) {
+                res.headers_mut().append(SET_COOKIE, value);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for Cookies {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let jar = Jar::parse(&req, self.key.clone(), true);
+        req.set_cloneable_ext(jar);
+
+        let mut req = next.run(req).await;
+
+        if let Some(jar) = req.take_ext::<Jar>() {
+            write_delta(&mut req, &jar.jar);
+        }
+
+        req
+    }
+}
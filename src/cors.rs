@@ -0,0 +1,454 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hyper::header::{
+    HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+};
+use hyper::{HeaderMap, Method, StatusCode};
+
+use crate::{Handler, Next, Request, Response};
+
+/// Middleware implementing [CORS](https://fetch.spec.whatwg.org/#http-cors-protocol).
+///
+/// Preflight requests are answered directly, so this should be placed before any
+/// router. Responses to other cross-origin requests are decorated with the
+/// appropriate headers.
+///
+/// By default no origins are allowed, and the `GET`, `HEAD` and `POST` methods are
+/// allowed for those that are.
+pub struct Cors {
+    origins: AllowOrigin,
+    methods: Vec<Method>,
+    headers: AllowHeaders,
+    exposed_headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+enum AllowOrigin {
+    Any,
+    List(Vec<HeaderValue>),
+    Predicate(Box<dyn Fn(&HeaderValue) -> bool + Send + Sync>),
+}
+
+enum AllowHeaders {
+    Any,
+    List(Vec<HeaderName>),
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            origins: AllowOrigin::List(vec![]),
+            methods: vec![Method::GET, Method::HEAD, Method::POST],
+            headers: AllowHeaders::List(vec![]),
+            exposed_headers: vec![],
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Cors::default()
+    }
+
+    /// Allows requests from any origin.
+    ///
+    /// # Panics
+    ///
+    /// Panics if credentials are allowed, as that would let any site make
+    /// authenticated requests on behalf of the user.
+    pub fn with_any_origin(mut self) -> Self {
+        assert!(
+            !self.credentials,
+            "credentials can't be allowed for any origin, list the allowed origins instead"
+        );
+        self.origins = AllowOrigin::Any;
+        self
+    }
+
+    /// Allows requests from the given origin, e.g. `https://example.com`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the origin is not a valid header value.
+    pub fn with_allowed_origin(self, origin: &str) -> Self {
+        self.with_allowed_origins(std::iter::once(origin))
+    }
+
+    /// Allows requests from each of the given origins.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any origin is not a valid header value.
+    pub fn with_allowed_origins<'a>(mut self, origins: impl IntoIterator<Item = &'a str>) -> Self {
+        let origins = origins
+            .into_iter()
+            .map(|origin| HeaderValue::from_str(origin).expect("invalid origin"));
+
+        match &mut self.origins {
+            AllowOrigin::List(list) => list.extend(origins),
+            _ => self.origins = AllowOrigin::List(origins.collect()),
+        }
+
+        self
+    }
+
+    /// Allows requests from origins for which the predicate returns `true`.
+    pub fn with_origin_predicate(
+        mut self,
+        predicate: impl Fn(&HeaderValue) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.origins = AllowOrigin::Predicate(Box::new(predicate));
+        self
+    }
+
+    pub fn with_allowed_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Allows the given request headers, in addition to the CORS-safelisted ones.
+    pub fn with_allowed_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.headers = AllowHeaders::List(headers.into_iter().collect());
+        self
+    }
+
+    /// Allows any request headers, by mirroring those asked for in preflight requests.
+    pub fn with_any_header(mut self) -> Self {
+        self.headers = AllowHeaders::Any;
+        self
+    }
+
+    /// Exposes the given response headers to scripts.
+    pub fn with_exposed_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.exposed_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Allows requests with credentials, i.e. cookies and authorization headers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any origin is allowed, as that would let any site make
+    /// authenticated requests on behalf of the user.
+    pub fn with_credentials(mut self, credentials: bool) -> Self {
+        assert!(
+            !credentials || !matches!(self.origins, AllowOrigin::Any),
+            "credentials can't be allowed for any origin, list the allowed origins instead"
+        );
+        self.credentials = credentials;
+        self
+    }
+
+    /// Sets how long the results of a preflight request can be cached for.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn is_origin_allowed(&self, origin: &HeaderValue) -> bool {
+        match &self.origins {
+            AllowOrigin::Any => true,
+            AllowOrigin::List(list) => list.contains(origin),
+            AllowOrigin::Predicate(predicate) => predicate(origin),
+        }
+    }
+
+    /// Whether responses are the same regardless of the request origin, in which case
+    /// `*` can be used instead of echoing the origin.
+    fn is_wildcard(&self) -> bool {
+        matches!(self.origins, AllowOrigin::Any)
+    }
+
+    fn set_origin_headers(&self, headers: &mut HeaderMap, origin: HeaderValue) {
+        if self.is_wildcard() {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        } else {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        }
+
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    fn preflight(&self, req: &Request, origin: HeaderValue) -> Response {
+        let mut res = Response::from(StatusCode::NO_CONTENT);
+
+        if !self.is_wildcard() {
+            res.add_vary(ORIGIN);
+        }
+
+        res.add_vary(ACCESS_CONTROL_REQUEST_METHOD);
+        res.add_vary(ACCESS_CONTROL_REQUEST_HEADERS);
+
+        let method = req
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|value| Method::from_bytes(value.as_bytes()).ok());
+
+        let method_allowed = method.is_some_and(|method| self.methods.contains(&method));
+
+        let requested_headers = req
+            .headers()
+            .get_all(ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| HeaderName::from_bytes(name.as_bytes()))
+            .collect::<Result<Vec<_>, _>>();
+
+        let headers_allowed = match (&self.headers, &requested_headers) {
+            (_, Err(_)) => false,
+            (AllowHeaders::Any, Ok(_)) => true,
+            (AllowHeaders::List(allowed), Ok(requested)) => {
+                requested.iter().all(|name| allowed.contains(name))
+            }
+        };
+
+        if !self.is_origin_allowed(&origin) || !method_allowed || !headers_allowed {
+            res.set_status(StatusCode::FORBIDDEN);
+            return res;
+        }
+
+        let headers = res.headers_mut();
+
+        self.set_origin_headers(headers, origin);
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, join(&self.methods));
+
+        let allowed_headers = match (&self.headers, requested_headers) {
+            (AllowHeaders::Any, Ok(requested)) => requested,
+            (AllowHeaders::List(allowed), _) => allowed.clone(),
+            (AllowHeaders::Any, Err(_)) => vec![],
+        };
+
+        if !allowed_headers.is_empty() {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, join(&allowed_headers));
+        }
+
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age.as_secs()));
+        }
+
+        res
+    }
+}
+
+fn join<T: AsRef<str>>(items: &[T]) -> HeaderValue {
+    let joined = items
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    HeaderValue::from_str(&joined).unwrap()
+}
+
+#[async_trait]
+impl Handler for Cors {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let origin = req.headers().get(ORIGIN).cloned();
+
+        let is_preflight = req.method() == Method::OPTIONS
+            && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+
+        if let (Some(origin), true) = (&origin, is_preflight) {
+            let res = self.preflight(&req, origin.clone());
+            req.set_res(res);
+            return req;
+        }
+
+        let mut req = next.run(req).await;
+
+        let res = match req.res_mut() {
+            Some(res) => res,
+            None => return req,
+        };
+
+        // Unless the response is the same for every origin, caches need to know that
+        // it depends on the request's origin, even when there isn't one.
+        if !self.is_wildcard() {
+            res.add_vary(ORIGIN);
+        }
+
+        match origin {
+            Some(origin) if self.is_origin_allowed(&origin) => {
+                let headers = res.headers_mut();
+
+                self.set_origin_headers(headers, origin);
+
+                if !self.exposed_headers.is_empty() {
+                    headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, join(&self.exposed_headers));
+                }
+            }
+            _ => {}
+        }
+
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::VARY;
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    const ORIGIN_URL: &str = "https://example.com";
+
+    /// Sends a request through `cors` to an endpoint that responds with `200 OK`.
+    async fn run(cors: &Cors, method: Method, headers: &[(HeaderName, &str)]) -> Response {
+        let mut req = hyper::Request::builder().method(method).uri("/");
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+        let req = Request::new(req.body(Body::empty()).unwrap(), None);
+
+        let endpoint = NextFn(|mut req: Request| async move {
+            req.set_res(StatusCode::OK);
+            req
+        });
+
+        cors.run(req, &endpoint).await.take_res().unwrap()
+    }
+
+    async fn preflight(cors: &Cors, origin: &str, method: &str, headers: &str) -> Response {
+        let mut request_headers = vec![(ORIGIN, origin), (ACCESS_CONTROL_REQUEST_METHOD, method)];
+        if !headers.is_empty() {
+            request_headers.push((ACCESS_CONTROL_REQUEST_HEADERS, headers));
+        }
+
+        run(cors, Method::OPTIONS, &request_headers).await
+    }
+
+    fn varies(res: &Response) -> Vec<&str> {
+        res.headers()
+            .get_all(VARY)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect()
+    }
+
+    fn cors() -> Cors {
+        Cors::new()
+            .with_allowed_origin(ORIGIN_URL)
+            .with_allowed_methods([Method::GET, Method::PUT])
+            .with_allowed_headers([
+                HeaderName::from_static("content-type"),
+                HeaderName::from_static("x-custom"),
+            ])
+            .with_max_age(Duration::from_secs(600))
+    }
+
+    #[tokio::test]
+    async fn allows_preflight() {
+        let res = preflight(&cors(), ORIGIN_URL, "PUT", "X-Custom, content-type").await;
+        let headers = res.headers();
+
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN_URL);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type, x-custom"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+        assert_eq!(
+            varies(&res),
+            [
+                "origin",
+                "access-control-request-method",
+                "access-control-request-headers"
+            ]
+        );
+
+        // Any headers are allowed by mirroring those requested.
+        let cors = cors().with_any_header();
+        let res = preflight(&cors, ORIGIN_URL, "GET", "x-a, x-b").await;
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_HEADERS], "x-a, x-b");
+    }
+
+    #[tokio::test]
+    async fn rejects_preflight() {
+        let rejected = [
+            preflight(&cors(), "https://evil.com", "PUT", "").await,
+            preflight(&cors(), ORIGIN_URL, "DELETE", "").await,
+            preflight(&cors(), ORIGIN_URL, "PUT", "x-custom, x-other").await,
+            preflight(&cors(), ORIGIN_URL, "PUT", "bad header").await,
+        ];
+
+        for res in &rejected {
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            assert!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+            assert!(res.headers().get(ACCESS_CONTROL_ALLOW_METHODS).is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn decorates_simple_requests() {
+        let cors = cors()
+            .with_credentials(true)
+            .with_exposed_headers([HeaderName::from_static("x-total")]);
+
+        let res = run(&cors, Method::GET, &[(ORIGIN, ORIGIN_URL)]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN_URL);
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(res.headers()[ACCESS_CONTROL_EXPOSE_HEADERS], "x-total");
+        assert_eq!(varies(&res), ["origin"]);
+
+        // Other origins and same-origin requests still vary by origin.
+        for headers in [&[(ORIGIN, "https://evil.com")][..], &[]] {
+            let res = run(&cors, Method::GET, headers).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+            assert!(res
+                .headers()
+                .get(ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .is_none());
+            assert_eq!(varies(&res), ["origin"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn any_origin_is_a_wildcard() {
+        let cors = Cors::new().with_any_origin();
+
+        let res = run(&cors, Method::GET, &[(ORIGIN, ORIGIN_URL)]).await;
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(varies(&res).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "credentials can't be allowed for any origin")]
+    fn any_origin_with_credentials_panics() {
+        let _ = Cors::new().with_any_origin().with_credentials(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "credentials can't be allowed for any origin")]
+    fn credentials_with_any_origin_panics() {
+        let _ = Cors::new().with_credentials(true).with_any_origin();
+    }
+
+    #[test]
+    fn listed_origins_allow_credentials() {
+        let cors = Cors::new()
+            .with_allowed_origin("https://example.com")
+            .with_credentials(true);
+        assert!(!cors.is_wildcard());
+    }
+}
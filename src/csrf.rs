@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderName, CONTENT_LENGTH, CONTENT_TYPE, HOST, ORIGIN, REFERER};
+use hyper::{Method, StatusCode, Uri};
+use serde_json::Value;
+
+use crate::cookies::{Cookie, CookieJar, CookieRequestExt, Key, SameSite};
+use crate::error::Error;
+use crate::session::SessionRequestExt;
+use crate::{Handler, Next, Request, Responder};
+
+/// Middleware that protects against cross-site request forgery.
+///
+/// Every request is given a token, which handlers can get with
+/// [`CsrfRequestExt::csrf_token`] to include in forms or pass to scripts. Requests
+/// with unsafe methods must send the token back, either in the `X-CSRF-Token` header
+/// or in a `csrf_token` field of a URL encoded form body, and must not come from a
+/// different origin according to their `Origin` or `Referer` header.
+///
+/// By default the token is kept in a `csrf_token` cookie, which scripts can read
+/// (the double-submit cookie pattern). The token is signed with the key given to
+/// [`Csrf::new`], so that a subdomain that can set cookies can't make up a matching
+/// cookie and token. With [`Csrf::with_session`] it is kept in the session instead,
+/// in which case this must be placed after [`Session`], and a token is only saved
+/// in the session once a handler has read it.
+///
+/// Form bodies are read up to 64 KiB to look for the token; larger forms must send
+/// it in the header.
+///
+/// [`Session`]: crate::session::Session
+pub struct Csrf {
+    storage: Storage,
+    header: HeaderName,
+    field: String,
+    max_form_size: u64,
+    trusted_origins: Vec<String>,
+}
+
+enum Storage {
+    Cookie(Cookie<'static>, Key),
+    Session(String),
+}
+
+impl Csrf {
+    /// Creates the middleware, signing token cookies with the given key.
+    pub fn new(key: Key) -> Self {
+        let cookie = Cookie::build(("csrf_token", ""))
+            .path("/")
+            .secure(true)
+            .same_site(SameSite::Strict)
+            .build();
+
+        Csrf {
+            storage: Storage::Cookie(cookie, key),
+            header: HeaderName::from_static("x-csrf-token"),
+            field: "csrf_token".to_owned(),
+            max_form_size: 64 * 1024,
+            trusted_origins: vec![],
+        }
+    }
+
+    /// Keeps the token in the session under the given key, rather than in a cookie.
+    pub fn with_session(mut self, key: impl Into<String>) -> Self {
+        self.storage = Storage::Session(key.into());
+        self
+    }
+
+    /// Sets the name of the cookie the token is kept in. Defaults to `csrf_token`.
+    pub fn with_cookie_name(mut self, name: impl Into<String>) -> Self {
+        if let Storage::Cookie(cookie, _) = &mut self.storage {
+            cookie.set_name(name.into());
+        }
+
+        self
+    }
+
+    /// Sets whether the token cookie is only sent over HTTPS. Defaults to `true`.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        if let Storage::Cookie(cookie, _) = &mut self.storage {
+            cookie.set_secure(secure);
+        }
+
+        self
+    }
+
+    /// Sets the header the token is read from. Defaults to `X-CSRF-Token`.
+    pub fn with_header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Sets the form field the token is read from. Defaults to `csrf_token`.
+    pub fn with_field_name(mut self, field: impl Into<String>) -> Self {
+        self.field = field.into();
+        self
+    }
+
+    /// Sets the largest form body that is read to look for the token, in bytes.
+    /// Defaults to 64 KiB.
+    pub fn with_max_form_size(mut self, max_size: u64) -> Self {
+        self.max_form_size = max_size;
+        self
+    }
+
+    /// Allows unsafe requests from another origin, e.g. `https://example.com`. They
+    /// still need a valid token.
+    pub fn with_trusted_origin(mut self, origin: impl Into<String>) -> Self {
+        self.trusted_origins.push(origin.into());
+        self
+    }
+
+    /// The token stored for the client, if any. An empty token is treated as missing,
+    /// so that it's replaced and can never match an empty submitted token, as is a
+    /// cookie with an invalid signature.
+    async fn stored_token(&self, req: &mut Request) -> Option<String> {
+        let token = match &self.storage {
+            Storage::Cookie(cookie, key) => req
+                .cookies()
+                .get(cookie.name())
+                .map(|cookie| cookie.value().to_owned())
+                .filter(|token| verify_signature(key, token)),
+            Storage::Session(key) => match req.session().await {
+                Ok(session) => session.get(key),
+                Err(err) => {
+                    log::error!("failed to load CSRF token from session: {}", err);
+                    None
+                }
+            },
+        };
+
+        token.filter(|token| !token.is_empty())
+    }
+
+    /// Checks that the request didn't come from another origin. Requests that have
+    /// neither an `Origin` nor a `Referer` header are let through, and left to the
+    /// token check. Without a `Host` header or an authority in the URI, as HTTP/2
+    /// requests have, only trusted origins are allowed.
+    fn check_origin(&self, req: &Request) -> Result<(), CsrfError> {
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| req.uri().authority().map(|authority| authority.as_str()));
+
+        let origin = req
+            .headers()
+            .get(ORIGIN)
+            .or_else(|| req.headers().get(REFERER))
+            .map(|value| value.to_str().ok().and_then(|s| s.parse::<Uri>().ok()));
+
+        let origin = match origin {
+            Some(Some(origin)) => origin,
+            Some(None) => return Err(CsrfError::CrossOrigin),
+            None => return Ok(()),
+        };
+
+        let same_host = match (origin.authority(), host) {
+            (Some(authority), Some(host)) => authority.as_str().eq_ignore_ascii_case(host),
+            _ => false,
+        };
+
+        let trusted = match (origin.scheme_str(), origin.authority()) {
+            (Some(scheme), Some(authority)) => {
+                let origin = format!("{}://{}", scheme, authority);
+                self.trusted_origins.contains(&origin)
+            }
+            _ => false,
+        };
+
+        if same_host || trusted {
+            Ok(())
+        } else {
+            Err(CsrfError::CrossOrigin)
+        }
+    }
+
+    async fn submitted_token(&self, req: &mut Request) -> Option<String> {
+        if let Some(token) = req.headers().get(&self.header) {
+            return token.to_str().ok().map(str::to_owned);
+        }
+
+        let is_form = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+        if !is_form {
+            return None;
+        }
+
+        let too_large = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+            .is_some_and(|len| len > self.max_form_size);
+
+        if too_large {
+            return None;
+        }
+
+        // The body is put back so that later handlers can still read the form. It's
+        // only lost if it's too large, in which case the request is rejected anyway.
+        let mut stream = req.body();
+        let mut body = Vec::new();
+
+        while let Some(chunk) = stream.data().await {
+            let chunk = chunk.ok()?;
+
+            if (body.len() + chunk.len()) as u64 > self.max_form_size {
+                return None;
+            }
+
+            body.extend_from_slice(&chunk);
+        }
+
+        let form =
+            serde_qs::Config::new(5, false).deserialize_bytes::<HashMap<String, Value>>(&body);
+        req.set_body(body);
+
+        match form.ok()?.remove(&self.field)? {
+            Value::String(token) => Some(token),
+            _ => None,
+        }
+    }
+
+    async fn verify(&self, req: &mut Request, token: Option<&str>) -> Result<(), CsrfError> {
+        self.check_origin(req)?;
+
+        let token = token.ok_or(CsrfError::MissingToken)?;
+        let submitted = self
+            .submitted_token(req)
+            .await
+            .filter(|token| !token.is_empty())
+            .ok_or(CsrfError::MissingToken)?;
+
+        if constant_time_eq(token.as_bytes(), submitted.as_bytes()) {
+            Ok(())
+        } else {
+            Err(CsrfError::InvalidToken)
+        }
+    }
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Signs a new token with the key. The signed value is the token itself, so that
+/// scripts can send back what they read from the cookie. Its base64 signature is
+/// made URL safe, so that it isn't percent-encoded in the cookie or form bodies.
+fn signed_token(key: &Key) -> String {
+    let mut jar = CookieJar::new();
+    jar.signed_mut(key).add(Cookie::new(
+        "csrf_token",
+        uuid::Uuid::new_v4().simple().to_string(),
+    ));
+
+    let token = jar.get("csrf_token").unwrap().value();
+    token.replace('+', "-").replace('/', "_").replace('=', ".")
+}
+
+fn verify_signature(key: &Key, token: &str) -> bool {
+    let token = token.replace('-', "+").replace('_', "/").replace('.', "=");
+
+    let mut jar = CookieJar::new();
+    jar.add_original(Cookie::new("csrf_token", token));
+    jar.signed(key).get("csrf_token").is_some()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// The reason a request was rejected by [`Csrf`]. Responds with a `403 Forbidden`
+/// [`Error`].
+#[derive(Debug, thiserror::Error)]
+pub enum CsrfError {
+    #[error("missing CSRF token")]
+    MissingToken,
+    #[error("invalid CSRF token")]
+    InvalidToken,
+    #[error("cross-origin request")]
+    CrossOrigin,
+}
+
+#[async_trait]
+impl Responder for CsrfError {
+    async fn respond_to(self, req: &mut Request) {
+        let err = Error::new(StatusCode::FORBIDDEN)
+            .with_message(self.to_string())
+            .with_source(self);
+        err.respond_to(req).await;
+    }
+}
+
+/// The request's token, and whether a handler has read it. The flag is shared with
+/// copies of the request, e.g. those kept by `Timeout`.
+#[derive(Clone)]
+struct CsrfToken(String, Arc<AtomicBool>);
+
+pub trait CsrfRequestExt {
+    /// The token that must be sent with unsafe requests.
+    fn csrf_token(&self) -> Option<&str>;
+}
+
+impl CsrfRequestExt for Request {
+    fn csrf_token(&self) -> Option<&str> {
+        self.ext::<CsrfToken>().map(|token| {
+            token.1.store(true, Ordering::Relaxed);
+            token.0.as_str()
+        })
+    }
+}
+
+#[async_trait]
+impl Handler for Csrf {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let token = self.stored_token(&mut req).await;
+
+        if !is_safe(req.method()) {
+            if let Err(err) = self.verify(&mut req, token.as_deref()).await {
+                err.respond_to(&mut req).await;
+                return req;
+            }
+        }
+
+        let (token, is_new) = match (token, &self.storage) {
+            (Some(token), _) => (token, false),
+            (None, Storage::Cookie(_, key)) => (signed_token(key), true),
+            (None, Storage::Session(_)) => (uuid::Uuid::new_v4().simple().to_string(), true),
+        };
+
+        let read = Arc::new(AtomicBool::new(false));
+        req.set_cloneable_ext(CsrfToken(token.clone(), read.clone()));
+
+        let mut req = next.run(req).await;
+
+        if !is_new {
+            return req;
+        }
+
+        match &self.storage {
+            Storage::Cookie(cookie, _) => {
+                let mut cookie = cookie.clone();
+                cookie.set_value(token);
+
+                req.cookies().add(cookie);
+                crate::cookies::write_cookies(&mut req);
+            }
+            Storage::Session(key) if read.load(Ordering::Relaxed) => {
+                if let Ok(session) = req.session().await {
+                    let _ = session.insert(key, &token);
+                }
+            }
+            Storage::Session(_) => {}
+        }
+
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{COOKIE, SET_COOKIE};
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::NextFn;
+    use crate::session::{MemoryStore, Session};
+
+    fn key() -> Key {
+        Key::from(&[7; 64])
+    }
+
+    /// Runs the request through `csrf` to an endpoint that echoes the body.
+    async fn run(csrf: impl Handler, req: hyper::Request<Body>) -> Request {
+        let endpoint = |mut req: Request| async move {
+            let body = req.body_bytes().await.unwrap();
+            req.set_res(Body::from(body));
+            req
+        };
+
+        (csrf, endpoint)
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await
+    }
+
+    async fn post(csrf: Csrf, cookie: &str, body: impl Into<Body>) -> Request {
+        let req = hyper::Request::post("/")
+            .header(COOKIE, cookie)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body.into())
+            .unwrap();
+
+        run(csrf, req).await
+    }
+
+    /// A request with a valid token from the given origin.
+    async fn post_from(csrf: Csrf, uri: &str, host: Option<&str>, origin: &str) -> Request {
+        let token = signed_token(&key());
+        let mut req = hyper::Request::post(uri)
+            .header(COOKIE, format!("csrf_token={}", token))
+            .header("x-csrf-token", token)
+            .header(ORIGIN, origin);
+
+        if let Some(host) = host {
+            req = req.header(HOST, host);
+        }
+
+        run(csrf, req.body(Body::empty()).unwrap()).await
+    }
+
+    fn set_cookies(req: &Request) -> Vec<String> {
+        req.res()
+            .unwrap()
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn accepts_matching_form_token() {
+        let token = signed_token(&key());
+        let cookie = format!("csrf_token={}", token);
+        let body = format!("csrf_token={}&a=1", token);
+        let mut req = post(Csrf::new(key()), &cookie, body.clone()).await;
+
+        let mut res = req.take_res().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(SET_COOKIE).is_none());
+
+        let body = hyper::body::to_bytes(res.take_body()).await.unwrap();
+        assert_eq!(&body[..], format!("csrf_token={}&a=1", token).as_bytes());
+    }
+
+    #[tokio::test]
+    async fn rejects_unsigned_tokens() {
+        let req = post(Csrf::new(key()), "csrf_token=abc", "csrf_token=abc").await;
+        assert_eq!(req.res().unwrap().status(), StatusCode::FORBIDDEN);
+
+        // Nor is a token signed with another key accepted.
+        let token = signed_token(&Key::from(&[8; 64]));
+        let cookie = format!("csrf_token={}", token);
+        let body = format!("csrf_token={}", token);
+
+        let req = post(Csrf::new(key()), &cookie, body).await;
+        assert_eq!(req.res().unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_tokens() {
+        let req = post(Csrf::new(key()), "csrf_token=", "csrf_token=").await;
+
+        let res = req.res().unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(res.headers().get(SET_COOKIE).is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_form() {
+        let token = signed_token(&key());
+        let cookie = format!("csrf_token={}", token);
+        let body = format!("csrf_token={}&a={}", token, "a".repeat(100));
+        let csrf = Csrf::new(key()).with_max_form_size(64);
+
+        let req = post(csrf, &cookie, body).await;
+        assert_eq!(req.res().unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn checks_origin() {
+        let status = |req: Request| req.res().unwrap().status();
+        let origin = "https://example.com";
+
+        let req = post_from(Csrf::new(key()), "/", Some("example.com"), origin).await;
+        assert_eq!(status(req), StatusCode::OK);
+
+        let req = post_from(Csrf::new(key()), "/", Some("evil.com"), origin).await;
+        assert_eq!(status(req), StatusCode::FORBIDDEN);
+
+        // Without a `Host` header, the URI's authority is used.
+        let uri = "https://example.com/";
+        let req = post_from(Csrf::new(key()), uri, None, origin).await;
+        assert_eq!(status(req), StatusCode::OK);
+
+        // And without either, only trusted origins are allowed.
+        let req = post_from(Csrf::new(key()), "/", None, origin).await;
+        assert_eq!(status(req), StatusCode::FORBIDDEN);
+
+        let csrf = Csrf::new(key()).with_trusted_origin(origin);
+        let req = post_from(csrf, "/", None, origin).await;
+        assert_eq!(status(req), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn sets_signed_token_cookie_once() {
+        let req = hyper::Request::new(Body::empty());
+        let req = run(Csrf::new(key()), req).await;
+
+        let token = req.csrf_token().unwrap().to_owned();
+        let cookies = set_cookies(&req);
+
+        assert!(verify_signature(&key(), &token));
+        assert_eq!(cookies.len(), 1);
+        assert!(cookies[0].starts_with(&format!("csrf_token={}", token)));
+    }
+
+    #[tokio::test]
+    async fn only_saves_session_token_when_read() {
+        let handler = |read: bool| {
+            let endpoint = move |mut req: Request| async move {
+                if read {
+                    req.csrf_token().unwrap();
+                }
+                req.set_res(StatusCode::OK);
+                req
+            };
+            let csrf = Csrf::new(key()).with_session("csrf_token");
+            (Session::new(MemoryStore::new()), (csrf, endpoint))
+        };
+
+        let req = hyper::Request::new(Body::empty());
+        let req = run(handler(false), req).await;
+        assert!(set_cookies(&req).is_empty());
+
+        let req = hyper::Request::new(Body::empty());
+        let req = run(handler(true), req).await;
+        let cookies = set_cookies(&req);
+
+        assert_eq!(cookies.len(), 1);
+        assert!(cookies[0].starts_with("session="));
+    }
+}
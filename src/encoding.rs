@@ -0,0 +1,140 @@
+use std::str::FromStr;
+
+use hyper::header::{HeaderValue, ACCEPT_ENCODING};
+use hyper::HeaderMap;
+
+/// A content coding, as used in the `Accept-Encoding` and `Content-Encoding` headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// The file extension conventionally used for files precompressed with this
+    /// encoding, if there is one.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Gzip => Some("gz"),
+            Encoding::Brotli => Some("br"),
+            Encoding::Zstd => Some("zst"),
+            Encoding::Identity | Encoding::Deflate => None,
+        }
+    }
+
+    pub(crate) fn to_header_value(self) -> HeaderValue {
+        HeaderValue::from_static(self.as_str())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown content coding")]
+pub struct UnknownEncoding;
+
+impl FromStr for Encoding {
+    type Err = UnknownEncoding;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let encoding = if s.eq_ignore_ascii_case("identity") {
+            Encoding::Identity
+        } else if s.eq_ignore_ascii_case("gzip") || s.eq_ignore_ascii_case("x-gzip") {
+            Encoding::Gzip
+        } else if s.eq_ignore_ascii_case("deflate") {
+            Encoding::Deflate
+        } else if s.eq_ignore_ascii_case("br") {
+            Encoding::Brotli
+        } else if s.eq_ignore_ascii_case("zstd") {
+            Encoding::Zstd
+        } else {
+            return Err(UnknownEncoding);
+        };
+
+        Ok(encoding)
+    }
+}
+
+/// A parsed `Accept-Encoding` header.
+#[derive(Debug, Default)]
+pub(crate) struct AcceptEncoding {
+    codings: Vec<(String, f32)>,
+}
+
+impl AcceptEncoding {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<AcceptEncoding> {
+        let mut values = headers.get_all(ACCEPT_ENCODING).iter().peekable();
+
+        values.peek()?;
+
+        let codings = values
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|item| {
+                let mut params = item.split(';');
+                let coding = params.next()?.trim();
+
+                if coding.is_empty() {
+                    return None;
+                }
+
+                let q = params
+                    .filter_map(|param| param.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                    .map_or(Some(1.0), |(_, q)| q.trim().parse::<f32>().ok())?;
+
+                Some((coding.to_ascii_lowercase(), q.clamp(0.0, 1.0)))
+            })
+            .collect();
+
+        Some(AcceptEncoding { codings })
+    }
+
+    /// Returns the quality value the client assigned to the given encoding.
+    pub(crate) fn quality(&self, encoding: Encoding) -> f32 {
+        let find = |name: &str| {
+            self.codings
+                .iter()
+                .find(|(coding, _)| coding == name)
+                .map(|&(_, q)| q)
+        };
+
+        let explicit = match encoding {
+            Encoding::Gzip => find("gzip").or_else(|| find("x-gzip")),
+            _ => find(encoding.as_str()),
+        };
+
+        match (explicit, find("*")) {
+            (Some(q), _) | (None, Some(q)) => q,
+            // Identity is always acceptable unless explicitly excluded.
+            (None, None) if encoding == Encoding::Identity => 1.0,
+            (None, None) => 0.0,
+        }
+    }
+
+    /// Returns the acceptable encodings out of `supported`, most preferred first.
+    /// Ties are broken by the order of `supported`.
+    pub(crate) fn preferred(&self, supported: &[Encoding]) -> Vec<Encoding> {
+        let mut ranked: Vec<_> = supported
+            .iter()
+            .map(|&encoding| (encoding, self.quality(encoding)))
+            .filter(|&(_, q)| q > 0.0)
+            .collect();
+
+        // This is a stable sort so equal qualities keep their relative order.
+        ranked.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+        ranked.into_iter().map(|(encoding, _)| encoding).collect()
+    }
+}
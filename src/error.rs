@@ -0,0 +1,507 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use async_trait::async_trait;
+use hyper::header::{HeaderValue, ACCEPT, CONTENT_TYPE};
+use hyper::StatusCode;
+
+use crate::{Handler, Next, Request, Responder, Response};
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// An error that can be returned from endpoints, with the status code and message to
+/// respond with, and the underlying error that caused it.
+///
+/// Any [`std::error::Error`] can be converted into an `Error` with
+/// [`Error::internal`], so `?` can be used in endpoints that return
+/// `Result<_, Error>`.
+///
+/// When used as a [`Responder`], the error is stored as a request extension, and a
+/// plain text response is set. [`ErrorHandler`] can then render a better response.
+#[derive(Debug)]
+pub struct Error {
+    status: StatusCode,
+    message: Option<String>,
+    source: Option<BoxError>,
+}
+
+impl Error {
+    pub fn new(status: StatusCode) -> Self {
+        Error {
+            status,
+            message: None,
+            source: None,
+        }
+    }
+
+    /// Creates a `500 Internal Server Error` caused by an unexpected error.
+    ///
+    /// In debug builds, the error and its sources are shown to clients to help with
+    /// debugging. In release builds, only the canonical reason is shown.
+    pub fn internal(source: impl Into<BoxError>) -> Self {
+        let err = Error::new(StatusCode::INTERNAL_SERVER_ERROR).with_source(source);
+
+        if cfg!(debug_assertions) {
+            let message = err
+                .chain()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": ");
+
+            err.with_message(message)
+        } else {
+            err
+        }
+    }
+
+    /// Sets the message shown to clients. Without one, the canonical reason for the
+    /// status is shown.
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Sets the underlying error. This is logged, but never shown to clients.
+    pub fn with_source(mut self, source: impl Into<BoxError>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The message shown to clients, if one was set.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_deref().map(|source| source as _)
+    }
+
+    /// Iterates over the underlying error and its sources.
+    pub fn chain(&self) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+        std::iter::successors(self.source(), |&err| err.source())
+    }
+
+    /// Returns the first error of type `E` in the chain.
+    pub fn downcast_ref<E: StdError + 'static>(&self) -> Option<&E> {
+        self.chain().find_map(|err| err.downcast_ref())
+    }
+
+    fn title(&self) -> &str {
+        self.status.canonical_reason().unwrap_or("Unknown Error")
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message().unwrap_or_else(|| self.title()))
+    }
+}
+
+impl<E: StdError + Send + Sync + 'static> From<E> for Error {
+    fn from(err: E) -> Self {
+        Error::internal(err)
+    }
+}
+
+#[async_trait]
+impl Responder for Error {
+    async fn respond_to(self, req: &mut Request) {
+        req.set_res(Response::from(self.status).with_body(self.to_string()));
+        req.set_ext(self);
+    }
+}
+
+type Mapper = Box<dyn Fn(&Error) -> Option<Error> + Send + Sync>;
+
+/// Middleware that renders responses for [`Error`]s stored by later handlers.
+///
+/// The response is plain text, HTML, or an
+/// [RFC 7807](https://datatracker.ietf.org/doc/html/rfc7807) `application/problem+json`
+/// document, depending on the request's `Accept` header. Server errors are logged
+/// along with their sources.
+///
+/// The error is left in the request's extensions, so outer handlers can inspect it.
+#[derive(Default)]
+pub struct ErrorHandler {
+    mappers: Vec<Mapper>,
+}
+
+impl ErrorHandler {
+    pub fn new() -> Self {
+        ErrorHandler::default()
+    }
+
+    /// Replaces errors caused by an `E` with the error returned by `mapper`, e.g. to
+    /// give them a more specific status. The original source is kept if the new
+    /// error doesn't have one.
+    pub fn with_mapper<E: StdError + 'static>(
+        mut self,
+        mapper: impl Fn(&E) -> Error + Send + Sync + 'static,
+    ) -> Self {
+        self.mappers
+            .push(Box::new(move |err| err.downcast_ref::<E>().map(&mapper)));
+        self
+    }
+
+    fn map(&self, mut err: Error) -> Error {
+        let mapped = self.mappers.iter().find_map(|mapper| mapper(&err));
+
+        match mapped {
+            Some(mut mapped) => {
+                if mapped.source.is_none() {
+                    mapped.source = err.source.take();
+                }
+
+                mapped
+            }
+            None => err,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Plain,
+    Problem,
+    Html,
+}
+
+impl Format {
+    /// The media types that each format can be served for, in order of preference
+    /// when the client doesn't care.
+    const ALL: &'static [(Format, &'static [&'static str])] = &[
+        (Format::Plain, &["text/plain"]),
+        (
+            Format::Problem,
+            &["application/problem+json", "application/json"],
+        ),
+        (Format::Html, &["text/html"]),
+    ];
+
+    fn negotiate(req: &Request) -> Format {
+        let ranges: Vec<(&str, f32)> = req
+            .headers()
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(parse_media_range)
+            .collect();
+
+        if ranges.is_empty() {
+            return Format::Plain;
+        }
+
+        let mut best = (Format::Plain, 0.0);
+
+        for (format, types) in Format::ALL {
+            let quality = types
+                .iter()
+                .map(|ty| quality(&ranges, ty))
+                .fold(0.0, f32::max);
+
+            if quality > best.1 {
+                best = (*format, quality);
+            }
+        }
+
+        best.0
+    }
+}
+
+fn parse_media_range(range: &str) -> Option<(&str, f32)> {
+    let mut parts = range.split(';').map(str::trim);
+    let media_type = parts.next().filter(|ty| !ty.is_empty())?;
+
+    let quality = parts
+        .filter_map(|param| param.strip_prefix("q="))
+        .find_map(|q| q.parse().ok())
+        .unwrap_or(1.0);
+
+    Some((media_type, quality))
+}
+
+/// The quality of the most specific media range that matches the media type.
+fn quality(ranges: &[(&str, f32)], media_type: &str) -> f32 {
+    let main_type = media_type.split('/').next().unwrap_or("");
+
+    let specificity = |range: &str| {
+        if range.eq_ignore_ascii_case(media_type) {
+            Some(2)
+        } else if range
+            .strip_suffix("/*")
+            .is_some_and(|ty| ty.eq_ignore_ascii_case(main_type))
+        {
+            Some(1)
+        } else if range == "*/*" {
+            Some(0)
+        } else {
+            None
+        }
+    };
+
+    ranges
+        .iter()
+        .filter_map(|(range, q)| specificity(range).map(|s| (s, *q)))
+        .max_by_key(|(s, _)| *s)
+        .map_or(0.0, |(_, q)| q)
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn render(err: &Error, req: &Request) -> Response {
+    let (content_type, body) = match Format::negotiate(req) {
+        Format::Plain => ("text/plain; charset=utf-8", err.to_string()),
+        Format::Problem => {
+            let mut problem = serde_json::json!({
+                "type": "about:blank",
+                "title": err.title(),
+                "status": err.status.as_u16(),
+                "instance": req.uri().path(),
+            });
+
+            if let Some(message) = err.message() {
+                problem["detail"] = message.into();
+            }
+
+            ("application/problem+json", problem.to_string())
+        }
+        Format::Html => {
+            let title = escape_html(&format!("{} {}", err.status.as_u16(), err.title()));
+            let message = err
+                .message()
+                .map(|message| format!("<p>{}</p>", escape_html(message)))
+                .unwrap_or_default();
+
+            let body = format!(
+                "<!DOCTYPE html>\n<html>\n<head><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n{1}\n</body>\n</html>\n",
+                title, message
+            );
+
+            ("text/html; charset=utf-8", body)
+        }
+    };
+
+    let mut res = Response::from(err.status).with_body(body);
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    res
+}
+
+fn log_error(err: &Error, req: &Request) {
+    if !err.status.is_server_error() {
+        return;
+    }
+
+    let mut line = format!("{} {}: {}", req.method(), req.uri().path(), err.status);
+
+    match err.source() {
+        Some(_) => {
+            for source in err.chain() {
+                line.push_str(": ");
+                line.push_str(&source.to_string());
+            }
+        }
+        None => {
+            line.push_str(": ");
+            line.push_str(&err.to_string());
+        }
+    }
+
+    log::error!("{}", line);
+}
+
+#[async_trait]
+impl Handler for ErrorHandler {
+    async fn run(&self, req: Request, next: &dyn Next) -> Request {
+        let mut req = next.run(req).await;
+
+        let err = match req.take_ext::<Error>() {
+            Some(err) => self.map(err),
+            None => return req,
+        };
+
+        log_error(&err, &req);
+
+        let res = render(&err, &req);
+        req.set_res(res);
+        req.set_ext(err);
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("failed to load user")]
+    struct LoadError(#[source] std::io::Error);
+
+    /// Runs a request accepting `accept` through an `ErrorHandler` to an endpoint
+    /// that fails with `err`, returning the request and the response's status, type
+    /// and body.
+    async fn handle(
+        handler: ErrorHandler,
+        accept: Option<&str>,
+        err: fn() -> Error,
+    ) -> (Request, StatusCode, String, String) {
+        let mut req = hyper::Request::get("/users/1");
+        if let Some(accept) = accept {
+            req = req.header(ACCEPT, accept);
+        }
+        let req = Request::new(req.body(Body::empty()).unwrap(), None);
+
+        let endpoint = move |mut req: Request| async move {
+            err().respond_to(&mut req).await;
+            req
+        };
+
+        let mut req = (handler, endpoint)
+            .run(req, &NextFn(|req| async move { req }))
+            .await;
+
+        let mut res = req.take_res().unwrap();
+        let content_type = res.headers()[CONTENT_TYPE].to_str().unwrap().to_owned();
+        let body = hyper::body::to_bytes(res.take_body()).await.unwrap();
+
+        (
+            req,
+            res.status(),
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    fn not_found() -> Error {
+        Error::new(StatusCode::NOT_FOUND)
+    }
+
+    #[tokio::test]
+    async fn negotiates_format() {
+        let cases = [
+            (None, "text/plain; charset=utf-8"),
+            (Some("image/png"), "text/plain; charset=utf-8"),
+            (Some("application/json"), "application/problem+json"),
+            (Some("text/html"), "text/html; charset=utf-8"),
+            (
+                Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+                "text/html; charset=utf-8",
+            ),
+            (
+                Some("text/html;q=0.5, application/problem+json"),
+                "application/problem+json",
+            ),
+            // The most specific range decides, so text/plain is refused here.
+            (
+                Some("application/json;q=0.2, text/*;q=0.5, text/plain;q=0"),
+                "text/html; charset=utf-8",
+            ),
+            (Some("*/*"), "text/plain; charset=utf-8"),
+        ];
+
+        for (accept, expected) in &cases {
+            let (_, status, content_type, _) =
+                handle(ErrorHandler::new(), *accept, not_found).await;
+
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(content_type, *expected, "Accept: {:?}", accept);
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_each_format() {
+        let err = || Error::new(StatusCode::NOT_FOUND).with_message("no such user");
+
+        let (_, _, _, body) = handle(ErrorHandler::new(), None, err).await;
+        assert_eq!(body, "no such user");
+
+        let (_, _, _, body) = handle(ErrorHandler::new(), None, not_found).await;
+        assert_eq!(body, "Not Found");
+
+        let (_, _, _, body) =
+            handle(ErrorHandler::new(), Some("application/problem+json"), err).await;
+        let problem: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            problem,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "instance": "/users/1",
+                "detail": "no such user",
+            })
+        );
+
+        let (_, _, _, body) = handle(ErrorHandler::new(), Some("text/html"), err).await;
+        assert!(body.contains("<title>404 Not Found</title>"));
+        assert!(body.contains("<p>no such user</p>"));
+    }
+
+    #[tokio::test]
+    async fn escapes_html() {
+        let err = || Error::new(StatusCode::BAD_REQUEST).with_message("<script>\"&'</script>");
+
+        let (_, _, _, body) = handle(ErrorHandler::new(), Some("text/html"), err).await;
+
+        assert!(body.contains("<p>&lt;script&gt;&quot;&amp;&#39;&lt;/script&gt;</p>"));
+        assert!(!body.contains("<script>"));
+    }
+
+    #[tokio::test]
+    async fn maps_errors() {
+        let handler = ErrorHandler::new().with_mapper(|_: &std::num::ParseIntError| {
+            Error::new(StatusCode::BAD_REQUEST).with_message("invalid user ID")
+        });
+        let err = || Error::from("x".parse::<u64>().unwrap_err());
+
+        let (req, status, _, body) = handle(handler, None, err).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "invalid user ID");
+
+        // The original source is kept.
+        let err = req.ext::<Error>().unwrap();
+        assert!(err.downcast_ref::<std::num::ParseIntError>().is_some());
+
+        // Other errors aren't mapped.
+        let handler = ErrorHandler::new()
+            .with_mapper(|_: &std::num::ParseIntError| Error::new(StatusCode::BAD_REQUEST));
+        let (_, status, _, _) = handle(handler, None, not_found).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn shows_source_chain_in_debug_builds() {
+        let err = || {
+            let io = std::io::Error::other("connection reset");
+            Error::internal(LoadError(io))
+        };
+
+        let (req, status, _, body) = handle(ErrorHandler::new(), None, err).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        if cfg!(debug_assertions) {
+            assert_eq!(body, "failed to load user: connection reset");
+        } else {
+            assert_eq!(body, "Internal Server Error");
+        }
+
+        let err = req.ext::<Error>().unwrap();
+        assert_eq!(err.chain().count(), 2);
+        assert!(err.downcast_ref::<std::io::Error>().is_some());
+    }
+}
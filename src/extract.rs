@@ -0,0 +1,386 @@
+mod path;
+
+use std::any::type_name;
+
+use async_trait::async_trait;
+use headers::{Header, HeaderMapExt};
+use hyper::header::{HeaderName, CONTENT_TYPE};
+use hyper::{HeaderMap, StatusCode};
+use percent_encoding::percent_decode_str;
+use routefinder::Captures;
+use serde::de::{self, DeserializeOwned};
+
+use crate::error::Error;
+use crate::query::{QueryError, QueryRequestExt};
+use crate::{Request, Responder};
+
+pub use crate::responder::Json;
+pub use crate::state::State;
+
+/// A value that can be extracted from a request, for use as an argument to an
+/// [`endpoint`](crate::endpoint).
+///
+/// If extraction fails, the rejection is used as the response, and the endpoint is
+/// not called.
+#[async_trait]
+pub trait FromRequest: Sized + Send {
+    type Rejection: Responder;
+
+    async fn from_request(req: &mut Request) -> Result<Self, Self::Rejection>;
+}
+
+/// The reason a value couldn't be extracted from a request. Responds with an
+/// [`Error`] with the status given by [`ExtractError::status`].
+#[derive(Debug, thiserror::Error)]
+pub enum ExtractError {
+    #[error("invalid path parameters: {0}")]
+    Path(#[source] serde::de::value::Error),
+    #[error("invalid query string: {0}")]
+    Query(#[source] QueryError),
+    #[error("expected request body with content type {0}")]
+    UnsupportedMediaType(&'static str),
+    #[error("failed to read request body: {0}")]
+    Body(#[source] hyper::Error),
+    #[error("invalid JSON body: {0}")]
+    Json(#[source] serde_json::Error),
+    #[error("invalid form body: {0}")]
+    Form(#[source] serde_qs::Error),
+    #[error("missing header: {0}")]
+    MissingHeader(HeaderName),
+    #[error("invalid header: {0}")]
+    InvalidHeader(HeaderName),
+    #[error("missing state: {0}")]
+    MissingState(&'static str),
+}
+
+impl ExtractError {
+    /// `415 Unsupported Media Type` for bodies with the wrong content type,
+    /// `422 Unprocessable Entity` for bodies that are well-formed but can't be
+    /// deserialized, `500 Internal Server Error` for missing state, and
+    /// `400 Bad Request` otherwise.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ExtractError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ExtractError::Json(err) if err.is_data() => StatusCode::UNPROCESSABLE_ENTITY,
+            ExtractError::Form(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ExtractError::MissingState(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[async_trait]
+impl Responder for ExtractError {
+    async fn respond_to(self, req: &mut Request) {
+        let mut err = Error::new(self.status());
+
+        // Missing state is a bug in the app, not something the client can fix.
+        if !err.status().is_server_error() {
+            err = err.with_message(self.to_string());
+        }
+
+        err.with_source(self).respond_to(req).await;
+    }
+}
+
+/// Extracts the parameters of the matched route, percent-decoded.
+///
+/// `T` can be a struct or map, to deserialize parameters by name, a tuple, to
+/// deserialize them in order, or a single value if the route has one parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Path<T> {
+    type Rejection = ExtractError;
+
+    async fn from_request(req: &mut Request) -> Result<Self, ExtractError> {
+        let captures = match req.ext::<Captures>() {
+            Some(captures) => captures.params(),
+            None => &[],
+        };
+
+        let decoded = captures
+            .iter()
+            .map(|capture| {
+                percent_decode_str(capture.value())
+                    .decode_utf8()
+                    .map(|value| (capture.name(), value))
+                    .map_err(|_| de::Error::custom("path parameter is not valid UTF-8"))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ExtractError::Path)?;
+
+        let params: Vec<(&str, &str)> = decoded
+            .iter()
+            .map(|(name, value)| (*name, value.as_ref()))
+            .collect();
+
+        path::from_params(&params)
+            .map(Path)
+            .map_err(ExtractError::Path)
+    }
+}
+
+/// Extracts the query string, using [`QueryRequestExt::query`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Query<T> {
+    type Rejection = ExtractError;
+
+    async fn from_request(req: &mut Request) -> Result<Self, ExtractError> {
+        req.query().map(Query).map_err(ExtractError::Query)
+    }
+}
+
+fn has_content_type(req: &Request, essence: &str, suffix: Option<&str>) -> bool {
+    let mime: mime::Mime = match req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+    {
+        Some(mime) => mime,
+        None => return false,
+    };
+
+    mime.essence_str() == essence
+        || suffix.is_some_and(|suffix| mime.suffix().is_some_and(|s| s == suffix))
+}
+
+/// Extracts a JSON request body. The request must have a JSON content type.
+#[async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Json<T> {
+    type Rejection = ExtractError;
+
+    async fn from_request(req: &mut Request) -> Result<Self, ExtractError> {
+        if !has_content_type(req, "application/json", Some("json")) {
+            return Err(ExtractError::UnsupportedMediaType("application/json"));
+        }
+
+        let body = req.body_bytes().await.map_err(ExtractError::Body)?;
+
+        serde_json::from_slice(&body)
+            .map(Json)
+            .map_err(ExtractError::Json)
+    }
+}
+
+/// Extracts a URL encoded form body. The request must have the content type
+/// `application/x-www-form-urlencoded`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Form<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Form<T> {
+    type Rejection = ExtractError;
+
+    async fn from_request(req: &mut Request) -> Result<Self, ExtractError> {
+        let content_type = mime::APPLICATION_WWW_FORM_URLENCODED.essence_str();
+
+        if !has_content_type(req, content_type, None) {
+            return Err(ExtractError::UnsupportedMediaType(content_type));
+        }
+
+        let body = req.body_bytes().await.map_err(ExtractError::Body)?;
+
+        serde_qs::Config::new(5, false)
+            .deserialize_bytes(&body)
+            .map(Form)
+            .map_err(ExtractError::Form)
+    }
+}
+
+/// Extracts a clone of the state set by the [`State`] handler.
+#[async_trait]
+impl<T: Clone + Send + Sync + 'static> FromRequest for State<T> {
+    type Rejection = ExtractError;
+
+    async fn from_request(req: &mut Request) -> Result<Self, ExtractError> {
+        req.ext::<T>()
+            .cloned()
+            .map(State)
+            .ok_or_else(|| ExtractError::MissingState(type_name::<T>()))
+    }
+}
+
+/// Extracts a typed header, e.g. `TypedHeader<headers::UserAgent>`. Use
+/// `Option<TypedHeader<H>>` for optional headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypedHeader<H>(pub H);
+
+#[async_trait]
+impl<H: Header + Send> FromRequest for TypedHeader<H> {
+    type Rejection = ExtractError;
+
+    async fn from_request(req: &mut Request) -> Result<Self, ExtractError> {
+        if !req.headers().contains_key(H::name()) {
+            return Err(ExtractError::MissingHeader(H::name().clone()));
+        }
+
+        req.headers()
+            .typed_get()
+            .map(TypedHeader)
+            .ok_or_else(|| ExtractError::InvalidHeader(H::name().clone()))
+    }
+}
+
+/// Extracts a copy of all of the request's headers.
+#[async_trait]
+impl FromRequest for HeaderMap {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request(req: &mut Request) -> Result<Self, Self::Rejection> {
+        Ok(req.headers().clone())
+    }
+}
+
+/// Extracts `T` if possible, ignoring the rejection otherwise.
+#[async_trait]
+impl<T: FromRequest> FromRequest for Option<T> {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request(req: &mut Request) -> Result<Self, Self::Rejection> {
+        Ok(T::from_request(req).await.ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Method};
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::handler::{Handler, NextFn};
+    use crate::router::Router;
+
+    #[derive(Deserialize)]
+    struct Page {
+        #[allow(dead_code)]
+        page: u32,
+    }
+
+    /// Routes a request to `/users/:name`, extracting `T` from it. Responds with the
+    /// value returned by `f`, or with the rejection.
+    async fn extract<T, F>(req: hyper::Request<Body>, f: F) -> (StatusCode, String)
+    where
+        T: FromRequest + 'static,
+        F: Fn(T) -> String + Copy + Send + Sync + 'static,
+    {
+        let endpoint = move |mut req: Request| async move {
+            match T::from_request(&mut req).await {
+                Ok(value) => {
+                    req.set_res(f(value));
+                }
+                Err(rejection) => rejection.respond_to(&mut req).await,
+            }
+            req
+        };
+
+        let router = Router::new().with(|r| {
+            r.route("/users/:name").any(endpoint);
+        });
+
+        let mut req = router
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await;
+
+        let mut res = req.take_res().unwrap();
+        let body = hyper::body::to_bytes(res.take_body()).await.unwrap();
+        (res.status(), String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn status<T: FromRequest + 'static>(req: hyper::Request<Body>) -> StatusCode {
+        extract(req, |_: T| String::new()).await.0
+    }
+
+    fn get(uri: &str) -> hyper::Request<Body> {
+        hyper::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn post(content_type: &str, body: &'static str) -> hyper::Request<Body> {
+        hyper::Request::builder()
+            .method(Method::POST)
+            .uri("/users/a")
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn path_is_percent_decoded() {
+        let (status, body) = extract(
+            get("/users/caf%C3%A9%20au%20lait"),
+            |Path(name): Path<String>| name,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "café au lait");
+    }
+
+    #[tokio::test]
+    async fn invalid_path_is_bad_request() {
+        assert_eq!(
+            status::<Path<String>>(get("/users/%FF")).await,
+            StatusCode::BAD_REQUEST
+        );
+
+        assert_eq!(
+            status::<Path<u32>>(get("/users/abc")).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_query_is_bad_request() {
+        assert_eq!(
+            status::<Query<Page>>(get("/users/a?page=abc")).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_content_type_is_unsupported() {
+        assert_eq!(
+            status::<Json<Page>>(post("text/plain", r#"{"page": 1}"#)).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        assert_eq!(
+            status::<Form<Page>>(post("application/json", "page=1")).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_bad_request() {
+        assert_eq!(
+            status::<Json<Page>>(post("application/json", r#"{"page""#)).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn unexpected_body_is_unprocessable() {
+        let json = post("application/json", r#"{"page": "abc"}"#);
+        assert_eq!(
+            status::<Json<Page>>(json).await,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        let form = post("application/x-www-form-urlencoded", "page=abc");
+        assert_eq!(
+            status::<Form<Page>>(form).await,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_state_is_server_error() {
+        let (status, body) = extract(get("/users/a"), |_: State<u32>| String::new()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!body.contains("u32"));
+    }
+}
@@ -1,4 +1,7 @@
 use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 
 use async_trait::async_trait;
 
@@ -30,13 +33,96 @@ pub trait Handler: Send + Sync + 'static {
     fn name(&self) -> &str {
         std::any::type_name::<Self>()
     }
+
+    /// Whether the handler only sequences other handlers, like tuples and `Vec`s do,
+    /// in which case it isn't given its own span and isn't blamed for panics.
+    fn is_composite(&self) -> bool {
+        false
+    }
+}
+
+/// Where a [`CatchPanic`] finds out which handler panicked. Handlers only record
+/// their name here while unwinding, so nothing is done for requests that don't
+/// panic.
+///
+/// [`CatchPanic`]: crate::catch_panic::CatchPanic
+#[derive(Clone, Default)]
+pub(crate) struct PanickedHandler(Arc<Mutex<Option<String>>>);
+
+impl PanickedHandler {
+    pub(crate) fn take(&self) -> Option<String> {
+        self.0.lock().ok()?.take()
+    }
+}
+
+/// A handler's future, which records the handler's name if it panics.
+struct Named<'a, F> {
+    name: &'a str,
+    panicked: Option<PanickedHandler>,
+    inner: F,
+}
+
+impl<F: Future + Unpin> Future for Named<'_, F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        struct Guard<'a>(&'a str, Option<&'a PanickedHandler>);
+
+        // Guards are dropped innermost first while unwinding, so only the first one
+        // records its name.
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                if let (true, Some(panicked)) = (std::thread::panicking(), self.1) {
+                    if let Ok(mut panicked) = panicked.0.lock() {
+                        panicked.get_or_insert_with(|| self.0.to_owned());
+                    }
+                }
+            }
+        }
+
+        let this = &mut *self;
+        let _guard = Guard(this.name, this.panicked.as_ref());
+        Pin::new(&mut this.inner).poll(cx)
+    }
+}
+
+/// Runs a handler, inside a span named after it if the `tracing` feature is enabled.
+pub(crate) async fn run_handler<H: Handler + ?Sized>(
+    handler: &H,
+    req: Request,
+    next: &dyn Next,
+) -> Request {
+    if handler.is_composite() {
+        return handler.run(req, next).await;
+    }
+
+    let run = Named {
+        name: handler.name(),
+        panicked: req.ext::<PanickedHandler>().cloned(),
+        inner: handler.run(req, next),
+    };
+
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument;
+
+        let span = tracing::debug_span!("handler", handler.name = handler.name());
+        run.instrument(span).await
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    run.await
 }
 
 #[async_trait]
 impl<A: Handler, B: Handler> Handler for (A, B) {
     async fn run(&self, req: Request, next: &dyn Next) -> Request {
         let (a, b) = self;
-        a.run(req, &NextFn(|req| b.run(req, next))).await
+        run_handler(a, req, &NextFn(|req| run_handler(b, req, next))).await
+    }
+
+    fn is_composite(&self) -> bool {
+        true
     }
 }
 
@@ -49,6 +135,10 @@ impl Handler for Box<dyn Handler> {
     fn name(&self) -> &str {
         self.as_ref().name()
     }
+
+    fn is_composite(&self) -> bool {
+        self.as_ref().is_composite()
+    }
 }
 
 #[async_trait]
@@ -68,13 +158,17 @@ impl<H: Handler> Handler for Vec<H> {
 
         async fn run<H: Handler>(slice: &[H], req: Request, next: &dyn Next) -> Request {
             match slice.split_first() {
-                Some((v, rest)) => v.run(req, &NextImpl { rest, next }).await,
+                Some((v, rest)) => run_handler(v, req, &NextImpl { rest, next }).await,
                 None => next.run(req).await,
             }
         }
 
         run(self, req, next).await
     }
+
+    fn is_composite(&self) -> bool {
+        true
+    }
 }
 
 #[async_trait]
@@ -98,3 +192,49 @@ macro_rules! compose {
         ($e, $crate::compose!($($es),+))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    async fn endpoint(req: Request) -> Request {
+        req
+    }
+
+    struct Panics;
+
+    #[async_trait]
+    impl Handler for Panics {
+        async fn run(&self, _: Request, _: &dyn Next) -> Request {
+            panic!("oops");
+        }
+    }
+
+    #[tokio::test]
+    async fn records_innermost_panicked_handler() {
+        use futures::FutureExt;
+
+        let panicked = PanickedHandler::default();
+        let mut req = Request::new(hyper::Request::new(hyper::Body::empty()), None);
+        req.set_ext(panicked.clone());
+
+        let handler = (crate::timeout::Timeout::new(Duration::from_secs(1)), Panics);
+        let run = run_handler(&handler, req, &NextFn(|req| async move { req }));
+        let result = std::panic::AssertUnwindSafe(run).catch_unwind().await;
+
+        assert!(result.is_err());
+        assert_eq!(panicked.take().as_deref(), Some(Panics.name()));
+    }
+
+    #[test]
+    fn recognises_composite_handlers() {
+        let boxed: Box<dyn Handler> = Box::new((endpoint, endpoint));
+
+        assert!((endpoint, endpoint).is_composite());
+        assert!(vec![endpoint].is_composite());
+        assert!(boxed.is_composite());
+        assert!(!endpoint.is_composite());
+    }
+}
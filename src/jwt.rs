@@ -0,0 +1,636 @@
+use std::error::Error;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use headers::authorization::{Bearer, Credentials};
+use headers::Authorization;
+use hyper::header::AUTHORIZATION;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet, PublicKeyUse};
+use jsonwebtoken::{Header, Validation};
+use serde::de::DeserializeOwned;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::auth::{has_scheme, quote, set_principal, unauthorized};
+use crate::{Handler, Next, Request, StatusCode};
+
+pub use jsonwebtoken::{Algorithm, DecodingKey};
+
+/// The algorithms that tokens may be signed with. Anything else, in particular
+/// `none`, is rejected.
+const ALGORITHMS: &[Algorithm] = &[
+    Algorithm::HS256,
+    Algorithm::RS256,
+    Algorithm::ES256,
+    Algorithm::EdDSA,
+];
+
+/// The minimum time between fetches of a JWKS, which is refetched when a token is
+/// signed with an unknown key in case the keys have been rotated.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwtError {
+    #[error("unsupported algorithm {0:?}")]
+    UnsupportedAlgorithm(Algorithm),
+    #[error("no key found for token")]
+    UnknownKey,
+    #[error("invalid token")]
+    Invalid(#[from] jsonwebtoken::errors::Error),
+    #[error("failed to load JWKS")]
+    Jwks(#[source] Box<dyn Error + Send + Sync>),
+}
+
+/// Fetches a JWKS document from a URL.
+///
+/// This is implemented for async functions and closures that take the URL and
+/// return the body of the response, so any HTTP client can be used.
+#[async_trait]
+pub trait JwksFetcher: Send + Sync + 'static {
+    async fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+}
+
+#[async_trait]
+impl<F, Fut> JwksFetcher for F
+where
+    F: Fn(String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Vec<u8>, Box<dyn Error + Send + Sync>>> + Send,
+{
+    async fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self(url.to_owned()).await
+    }
+}
+
+struct JwtKey {
+    id: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+impl JwtKey {
+    fn from_jwk(jwk: &Jwk) -> Option<Self> {
+        if let Some(PublicKeyUse::Encryption) = jwk.common.public_key_use {
+            return None;
+        }
+
+        let algorithm = match (jwk.common.key_algorithm, &jwk.algorithm) {
+            (Some(algorithm), _) => algorithm.to_string().parse().ok()?,
+            (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+            (None, AlgorithmParameters::EllipticCurve(params))
+                if params.curve == EllipticCurve::P256 =>
+            {
+                Algorithm::ES256
+            }
+            (None, AlgorithmParameters::OctetKeyPair(params))
+                if params.curve == EllipticCurve::Ed25519 =>
+            {
+                Algorithm::EdDSA
+            }
+            (None, AlgorithmParameters::OctetKey(_)) => Algorithm::HS256,
+            _ => return None,
+        };
+
+        if !ALGORITHMS.contains(&algorithm) {
+            return None;
+        }
+
+        Some(JwtKey {
+            id: jwk.common.key_id.clone(),
+            algorithm,
+            key: DecodingKey::from_jwk(jwk).ok()?,
+        })
+    }
+
+    fn matches(&self, header: &Header) -> bool {
+        let id_matches = match (&self.id, &header.kid) {
+            (Some(id), Some(kid)) => id == kid,
+            _ => true,
+        };
+
+        id_matches && self.algorithm == header.alg
+    }
+}
+
+enum JwksSource {
+    File(PathBuf),
+    Url(String, Box<dyn JwksFetcher>),
+}
+
+struct Jwks {
+    source: JwksSource,
+    /// The keys and when they were loaded, or `None` until they first are.
+    keys: RwLock<Option<(Arc<Vec<JwtKey>>, Instant)>>,
+    /// Held while loading, so that only one request loads the keys at a time.
+    loading: Mutex<()>,
+}
+
+impl Jwks {
+    fn new(source: JwksSource) -> Self {
+        Jwks {
+            source,
+            keys: RwLock::new(None),
+            loading: Mutex::new(()),
+        }
+    }
+
+    /// Returns the keys, loading them if they haven't been yet, or reloading them if
+    /// `refresh` is set and they haven't been loaded recently.
+    ///
+    /// Requests that want the keys while they're being reloaded are given the old
+    /// ones, and only wait if there are none yet.
+    async fn keys(&self, refresh: bool) -> Result<Arc<Vec<JwtKey>>, Box<dyn Error + Send + Sync>> {
+        if let Some(keys) = self.cached(refresh) {
+            return Ok(keys);
+        }
+
+        let _loading = match self.loading.try_lock() {
+            Ok(loading) => loading,
+            Err(_) => match self.cached(false) {
+                Some(keys) => return Ok(keys),
+                None => self.loading.lock().await,
+            },
+        };
+
+        // They may have been loaded by the request that held the lock.
+        if let Some(keys) = self.cached(refresh) {
+            return Ok(keys);
+        }
+
+        // Failed loads aren't recorded, so they're retried by the next request.
+        let keys = Arc::new(self.load().await?);
+        *self.keys.write().unwrap() = Some((keys.clone(), Instant::now()));
+        Ok(keys)
+    }
+
+    /// The loaded keys, unless they're due to be reloaded.
+    fn cached(&self, refresh: bool) -> Option<Arc<Vec<JwtKey>>> {
+        let keys = self.keys.read().unwrap();
+        let (keys, loaded_at) = keys.as_ref()?;
+
+        if refresh && loaded_at.elapsed() >= JWKS_REFRESH_INTERVAL {
+            return None;
+        }
+
+        Some(keys.clone())
+    }
+
+    async fn load(&self) -> Result<Vec<JwtKey>, Box<dyn Error + Send + Sync>> {
+        let bytes = match &self.source {
+            JwksSource::File(path) => tokio::fs::read(path).await?,
+            JwksSource::Url(url, fetcher) => fetcher.fetch(url).await?,
+        };
+
+        let jwks = serde_json::from_slice::<JwkSet>(&bytes)?;
+        Ok(jwks.keys.iter().filter_map(JwtKey::from_jwk).collect())
+    }
+}
+
+/// Middleware that authenticates requests with JSON Web Tokens sent as bearer
+/// tokens.
+///
+/// Tokens must be signed with HS256, RS256, ES256 or EdDSA using one of the
+/// configured keys, or a key from the configured JWKS. Tokens must have an `exp`
+/// claim unless [`Jwt::without_expiry`] is used, and the `nbf` claim is checked if
+/// present. If an issuer or audience is configured, tokens must have a matching
+/// `iss` or `aud` claim.
+///
+/// The claims are deserialized into `T`, which later handlers can retrieve with
+/// [`AuthRequestExt::principal`].
+///
+/// [`AuthRequestExt::principal`]: crate::auth::AuthRequestExt::principal
+pub struct Jwt<T> {
+    keys: Vec<JwtKey>,
+    jwks: Option<Jwks>,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    require_exp: bool,
+    leeway: Duration,
+    realm: String,
+    optional: bool,
+    _claims: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned + Send + Sync + 'static> Default for Jwt<T> {
+    fn default() -> Self {
+        Jwt {
+            keys: vec![],
+            jwks: None,
+            issuers: vec![],
+            audiences: vec![],
+            require_exp: true,
+            leeway: Duration::from_secs(60),
+            realm: "atium".to_owned(),
+            optional: false,
+            _claims: PhantomData,
+        }
+    }
+}
+
+impl<T: DeserializeOwned + Send + Sync + 'static> Jwt<T> {
+    pub fn new() -> Self {
+        Jwt::default()
+    }
+
+    /// Accepts tokens signed with the given key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the algorithm is not HS256, RS256, ES256 or EdDSA.
+    pub fn with_key(self, algorithm: Algorithm, key: DecodingKey) -> Self {
+        self.add_key(None, algorithm, key)
+    }
+
+    /// Accepts tokens signed with the given key, if their `kid` header is `id` or
+    /// missing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the algorithm is not HS256, RS256, ES256 or EdDSA.
+    pub fn with_key_id(
+        self,
+        id: impl Into<String>,
+        algorithm: Algorithm,
+        key: DecodingKey,
+    ) -> Self {
+        self.add_key(Some(id.into()), algorithm, key)
+    }
+
+    fn add_key(mut self, id: Option<String>, algorithm: Algorithm, key: DecodingKey) -> Self {
+        assert!(
+            ALGORITHMS.contains(&algorithm),
+            "unsupported algorithm {:?}",
+            algorithm
+        );
+
+        self.keys.push(JwtKey { id, algorithm, key });
+        self
+    }
+
+    /// Accepts tokens signed with keys from a JWKS file.
+    pub fn with_jwks_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.jwks = Some(Jwks::new(JwksSource::File(path.into())));
+        self
+    }
+
+    /// Accepts tokens signed with keys from a JWKS document fetched from a URL, e.g.
+    /// an identity provider's `jwks_uri`.
+    pub fn with_jwks_url(mut self, url: impl Into<String>, fetcher: impl JwksFetcher) -> Self {
+        self.jwks = Some(Jwks::new(JwksSource::Url(url.into(), Box::new(fetcher))));
+        self
+    }
+
+    /// Only accepts tokens issued by the given issuer, or any of the issuers if
+    /// called more than once.
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuers.push(issuer.into());
+        self
+    }
+
+    /// Only accepts tokens intended for the given audience, or any of the audiences
+    /// if called more than once.
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Accepts tokens without an `exp` claim, which never expire. Tokens that have
+    /// one are still checked.
+    pub fn without_expiry(mut self) -> Self {
+        self.require_exp = false;
+        self
+    }
+
+    /// Sets how much clock skew is tolerated when checking `exp` and `nbf`. Defaults
+    /// to 60 seconds.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Sets the realm sent in the `WWW-Authenticate` challenge.
+    pub fn with_realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    /// Lets requests without a bearer token through anonymously, including those with
+    /// an `Authorization` header of another scheme. Requests with an invalid token, or
+    /// a Bearer header that can't be parsed, are still rejected.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = self.leeway.as_secs();
+        validation.validate_nbf = true;
+        validation.required_spec_claims.clear();
+
+        // Claims that aren't required are only checked if the token has them.
+        if self.require_exp {
+            validation.required_spec_claims.insert("exp".to_owned());
+        }
+
+        if !self.issuers.is_empty() {
+            validation.set_issuer(&self.issuers);
+            validation.required_spec_claims.insert("iss".to_owned());
+        }
+
+        if self.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audiences);
+            validation.required_spec_claims.insert("aud".to_owned());
+        }
+
+        validation
+    }
+
+    /// Validates a token and returns its claims.
+    async fn validate(&self, token: &str) -> Result<T, JwtError> {
+        let header = jsonwebtoken::decode_header(token)?;
+
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(JwtError::UnsupportedAlgorithm(header.alg));
+        }
+
+        let validation = self.validation(header.alg);
+        let result = decode(token, &header, &validation, &self.keys);
+
+        let jwks = match (&self.jwks, result) {
+            (Some(jwks), Err(JwtError::UnknownKey)) => jwks,
+            (_, result) => return result,
+        };
+
+        let keys = jwks.keys(false).await.map_err(JwtError::Jwks)?;
+
+        match decode(token, &header, &validation, &keys) {
+            // The keys may have been rotated. If they can't be reloaded, the token is
+            // rejected rather than failing the request, as the old keys still work.
+            Err(JwtError::UnknownKey) => match jwks.keys(true).await {
+                Ok(keys) => decode(token, &header, &validation, &keys),
+                Err(err) => {
+                    log::error!("failed to reload JWKS: {}", err);
+                    Err(JwtError::UnknownKey)
+                }
+            },
+            result => result,
+        }
+    }
+}
+
+/// Decodes a token with the first of the keys that it was signed with.
+fn decode<T: DeserializeOwned>(
+    token: &str,
+    header: &Header,
+    validation: &Validation,
+    keys: &[JwtKey],
+) -> Result<T, JwtError> {
+    let mut result = Err(JwtError::UnknownKey);
+
+    for key in keys.iter().filter(|key| key.matches(header)) {
+        result = jsonwebtoken::decode(token, &key.key, validation)
+            .map(|data| data.claims)
+            .map_err(JwtError::from);
+
+        match &result {
+            Err(JwtError::Invalid(err)) if *err.kind() == ErrorKind::InvalidSignature => {}
+            _ => break,
+        }
+    }
+
+    result
+}
+
+#[async_trait]
+impl<T: DeserializeOwned + Send + Sync + 'static> Handler for Jwt<T> {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let token = match req.header::<Authorization<Bearer>>() {
+            Some(Authorization(bearer)) => bearer,
+            None if self.optional && !has_scheme(&req, Bearer::SCHEME) => {
+                return next.run(req).await;
+            }
+            None if req.headers().contains_key(AUTHORIZATION) => {
+                let challenge = format!(
+                    "Bearer realm={}, error=\"invalid_request\"",
+                    quote(&self.realm)
+                );
+                unauthorized(&mut req, challenge);
+                return req;
+            }
+            None => {
+                unauthorized(&mut req, format!("Bearer realm={}", quote(&self.realm)));
+                return req;
+            }
+        };
+
+        match self.validate(token.token()).await {
+            Ok(claims) => {
+                set_principal(&mut req, claims);
+                next.run(req).await
+            }
+            Err(JwtError::Jwks(err)) => {
+                log::error!("failed to load JWKS: {}", err);
+                req.set_res(StatusCode::INTERNAL_SERVER_ERROR);
+                req
+            }
+            Err(_) => {
+                unauthorized(
+                    &mut req,
+                    format!(
+                        "Bearer realm={}, error=\"invalid_token\"",
+                        quote(&self.realm)
+                    ),
+                );
+                req
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use jsonwebtoken::EncodingKey;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    const SECRET: &[u8] = b"secret";
+
+    fn token(claims: Value) -> String {
+        let header = jsonwebtoken::Header::new(Algorithm::HS256);
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    fn exp() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 600
+    }
+
+    fn jwt() -> Jwt<Value> {
+        Jwt::new().with_key(Algorithm::HS256, DecodingKey::from_secret(SECRET))
+    }
+
+    #[tokio::test]
+    async fn requires_exp_unless_opted_out() {
+        let token = token(json!({ "sub": "a" }));
+
+        assert!(jwt().validate(&token).await.is_err());
+        assert!(jwt().without_expiry().validate(&token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn requires_configured_issuer_and_audience() {
+        let jwt = jwt().with_issuer("iss").with_audience("aud");
+
+        let missing_iss = token(json!({ "exp": exp(), "aud": "aud" }));
+        let missing_aud = token(json!({ "exp": exp(), "iss": "iss" }));
+        let wrong_aud = token(json!({ "exp": exp(), "iss": "iss", "aud": "other" }));
+        let valid = token(json!({ "exp": exp(), "iss": "iss", "aud": "aud" }));
+
+        assert!(jwt.validate(&missing_iss).await.is_err());
+        assert!(jwt.validate(&missing_aud).await.is_err());
+        assert!(jwt.validate(&wrong_aud).await.is_err());
+        assert!(jwt.validate(&valid).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn retries_failed_jwks_load() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher_calls = calls.clone();
+
+        let fetcher = move |_: String| {
+            let call = fetcher_calls.fetch_add(1, Ordering::SeqCst);
+
+            async move {
+                if call == 0 {
+                    return Err("unavailable".into());
+                }
+
+                let jwks = json!({ "keys": [{ "kty": "oct", "k": "c2VjcmV0" }] });
+                Ok(serde_json::to_vec(&jwks).unwrap())
+            }
+        };
+
+        let jwt = Jwt::<Value>::new().with_jwks_url("https://example.com/jwks", fetcher);
+        let token = token(json!({ "exp": exp() }));
+
+        assert!(matches!(jwt.validate(&token).await, Err(JwtError::Jwks(_))));
+        assert!(jwt.validate(&token).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    /// A fetcher that takes a second to return a JWKS with the key `a`, and fails
+    /// once it has succeeded `ok` times.
+    fn slow_fetcher(calls: Arc<AtomicUsize>, ok: usize) -> impl JwksFetcher {
+        move |_: String| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+
+            async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+
+                if call >= ok {
+                    return Err("unavailable".into());
+                }
+
+                let jwks = json!({ "keys": [{ "kty": "oct", "k": "c2VjcmV0", "kid": "a" }] });
+                Ok(serde_json::to_vec(&jwks).unwrap())
+            }
+        }
+    }
+
+    fn token_with_kid(kid: &str) -> String {
+        let mut header = jsonwebtoken::Header::new(Algorithm::HS256);
+        header.kid = Some(kid.to_owned());
+        let claims = json!({ "exp": exp() });
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn loads_jwks_once_at_a_time() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwt = Jwt::<Value>::new()
+            .with_jwks_url("https://example.com/jwks", slow_fetcher(calls.clone(), 2));
+        let (a, b, c) = (
+            token_with_kid("a"),
+            token_with_kid("b"),
+            token_with_kid("c"),
+        );
+
+        let (first, second) = futures::join!(jwt.validate(&a), jwt.validate(&a));
+        assert!(first.is_ok() && second.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Unknown keys only cause one reload, and other requests get the old keys
+        // while it's in progress.
+        tokio::time::advance(JWKS_REFRESH_INTERVAL).await;
+        let (b, c, a) = futures::join!(jwt.validate(&b), jwt.validate(&c), jwt.validate(&a));
+
+        assert!(matches!(b, Err(JwtError::UnknownKey)));
+        assert!(matches!(c, Err(JwtError::UnknownKey)));
+        assert!(a.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_jwks_reload_rejects_unknown_key() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwt = Jwt::<Value>::new()
+            .with_jwks_url("https://example.com/jwks", slow_fetcher(calls.clone(), 1));
+
+        assert!(jwt.validate(&token_with_kid("a")).await.is_ok());
+        tokio::time::advance(JWKS_REFRESH_INTERVAL).await;
+
+        let req = hyper::Request::get("/")
+            .header(AUTHORIZATION, format!("Bearer {}", token_with_kid("b")))
+            .body(hyper::Body::empty())
+            .unwrap();
+        let req = jwt
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await;
+
+        assert_eq!(req.res().unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // The old keys are still used.
+        assert!(jwt.validate(&token_with_kid("a")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn optional_rejects_malformed_bearer_header() {
+        let run = |value: Option<&'static str>| async move {
+            let mut req = hyper::Request::builder();
+            if let Some(value) = value {
+                req = req.header(AUTHORIZATION, value);
+            }
+            let req = Request::new(req.body(hyper::Body::empty()).unwrap(), None);
+
+            let req = (jwt().optional(), |mut req: Request| async move {
+                req.set_res(StatusCode::OK);
+                req
+            })
+                .run(req, &NextFn(|req| async move { req }))
+                .await;
+            req.res().unwrap().status()
+        };
+
+        assert_eq!(run(None).await, StatusCode::OK);
+        assert_eq!(run(Some("Bearer")).await, StatusCode::UNAUTHORIZED);
+
+        // Other schemes may be handled by other middleware.
+        assert_eq!(run(Some("Basic dTpw")).await, StatusCode::OK);
+    }
+}
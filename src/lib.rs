@@ -1,23 +1,47 @@
 mod request;
 mod response;
 
+pub mod auth;
+pub mod catch_panic;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod concurrency;
+#[cfg(feature = "cookies")]
+pub mod cookies;
+pub mod cors;
+#[cfg(feature = "cookies")]
+pub mod csrf;
+pub mod encoding;
+pub mod error;
+pub mod extract;
 pub mod handler;
+#[cfg(feature = "jwt")]
+pub mod jwt;
 pub mod logger;
+pub mod metrics;
 pub mod query;
+pub mod rate_limit;
+pub mod request_id;
 pub mod respond;
 pub mod responder;
 pub mod router;
+pub mod security;
 pub mod server;
+#[cfg(feature = "cookies")]
+pub mod session;
 pub mod state;
+pub mod timeout;
+#[cfg(feature = "tracing")]
+pub mod trace;
 
 pub use handler::{Handler, Next};
-pub use request::Request;
+pub use request::{JsonBodyError, Request};
 pub use responder::Responder;
 pub use response::Response;
 pub use server::{run, ServerError};
 
 pub use async_trait::async_trait;
-pub use atium_macros::endpoint;
+pub use atium_macros::{connect, delete, endpoint, get, head, options, patch, post, put, trace};
 pub use hyper::body::Bytes;
 pub use hyper::{Body, StatusCode};
 
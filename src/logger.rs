@@ -1,32 +1,643 @@
-use std::marker::PhantomData;
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_LENGTH, REFERER, USER_AGENT};
+use hyper::{Method, StatusCode, Uri, Version};
 
 use crate::handler::Next;
+use crate::request_id::RequestIdRequestExt;
 use crate::{Handler, Request};
 
-#[derive(Default)]
-pub struct Logger(PhantomData<()>);
+/// Middleware that writes an access log entry for every request.
+pub struct Logger {
+    format: LogFormat,
+    sink: Box<dyn LogSink>,
+    sample_rate: f64,
+    excluded_paths: HashSet<String>,
+    counter: AtomicU64,
+    hasher: RandomState,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger {
+            format: LogFormat::default(),
+            sink: Box::new(LogCrateSink),
+            sample_rate: 1.0,
+            excluded_paths: HashSet::new(),
+            counter: AtomicU64::new(0),
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl Logger {
+    pub fn new() -> Self {
+        Logger::default()
+    }
+
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_sink(mut self, sink: impl LogSink) -> Self {
+        self.sink = Box::new(sink);
+        self
+    }
+
+    /// Only logs the given fraction (between 0 and 1) of requests. Server errors and
+    /// requests without a response are always logged.
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Skips logging requests for the given path, e.g. a health check endpoint.
+    pub fn with_excluded_path(mut self, path: impl Into<String>) -> Self {
+        self.excluded_paths.insert(path.into());
+        self
+    }
+
+    fn is_sampled(&self) -> bool {
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        (self.hasher.hash_one(n) as f64 / u64::MAX as f64) < self.sample_rate
+    }
+}
+
+/// The information about a request and its response that is available to log
+/// formats and sinks.
+pub struct LogRecord<'a> {
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub version: Version,
+    pub status: Option<StatusCode>,
+    pub latency: Duration,
+    pub response_size: Option<u64>,
+    pub remote_addr: Option<SocketAddr>,
+    pub user_agent: Option<&'a str>,
+    pub referer: Option<&'a str>,
+    /// The ID assigned by [`RequestId`], read from the header it was configured with.
+    ///
+    /// [`RequestId`]: crate::request_id::RequestId
+    pub request_id: Option<&'a str>,
+    pub time: SystemTime,
+}
+
+impl<'a> LogRecord<'a> {
+    fn from_request(req: &'a Request, time: SystemTime, latency: Duration) -> Self {
+        let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+        let response_size = req.res().and_then(|res| {
+            res.headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .or_else(|| res.body().size_hint().exact())
+        });
+
+        LogRecord {
+            method: req.method(),
+            uri: req.uri(),
+            version: req.version(),
+            status: req.res().map(|res| res.status()),
+            latency,
+            response_size,
+            remote_addr: req.remote_addr(),
+            user_agent: header(USER_AGENT.as_str()),
+            referer: header(REFERER.as_str()),
+            request_id: req.request_id(),
+            time,
+        }
+    }
+}
+
+/// Receives formatted access log entries.
+pub trait LogSink: Send + Sync + 'static {
+    fn log(&self, record: &LogRecord, line: &str);
+}
+
+/// Writes entries using the `log` crate, at a level chosen based on the response
+/// status.
+pub struct LogCrateSink;
+
+impl LogSink for LogCrateSink {
+    fn log(&self, record: &LogRecord, line: &str) {
+        match record.status {
+            None => log::error!("{}", line),
+            Some(status) if status.is_server_error() => log::error!("{}", line),
+            Some(status) if status.is_client_error() => log::warn!("{}", line),
+            Some(_) => log::info!("{}", line),
+        }
+    }
+}
+
+impl<F> LogSink for F
+where
+    F: Fn(&LogRecord, &str) + Send + Sync + 'static,
+{
+    fn log(&self, record: &LogRecord, line: &str) {
+        self(record, line)
+    }
+}
+
+/// The format in which access log entries are written.
+pub struct LogFormat(FormatKind);
+
+enum FormatKind {
+    Default,
+    Common,
+    Combined,
+    Json,
+    Custom(Vec<Segment>),
+}
+
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Method,
+    Uri,
+    Path,
+    Query,
+    Version,
+    Status,
+    Latency,
+    LatencyMs,
+    Size,
+    RemoteAddr,
+    RemoteIp,
+    UserAgent,
+    Referer,
+    RequestId,
+    Time,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        let field = match name {
+            "method" => Field::Method,
+            "uri" => Field::Uri,
+            "path" => Field::Path,
+            "query" => Field::Query,
+            "version" => Field::Version,
+            "status" => Field::Status,
+            "latency" => Field::Latency,
+            "latency_ms" => Field::LatencyMs,
+            "size" => Field::Size,
+            "remote_addr" => Field::RemoteAddr,
+            "remote_ip" => Field::RemoteIp,
+            "user_agent" => Field::UserAgent,
+            "referer" => Field::Referer,
+            "request_id" => Field::RequestId,
+            "time" => Field::Time,
+            _ => return None,
+        };
+
+        Some(field)
+    }
+
+    fn write(self, out: &mut String, record: &LogRecord) {
+        fn or_dash(out: &mut String, val: Option<impl std::fmt::Display>) {
+            match val {
+                Some(val) => write!(out, "{}", val).unwrap(),
+                None => out.push('-'),
+            }
+        }
+
+        match self {
+            Field::Method => out.push_str(record.method.as_str()),
+            Field::Uri => write!(out, "{}", record.uri).unwrap(),
+            Field::Path => out.push_str(record.uri.path()),
+            Field::Query => or_dash(out, record.uri.query()),
+            Field::Version => write!(out, "{:?}", record.version).unwrap(),
+            Field::Status => or_dash(out, record.status.map(|s| s.as_u16())),
+            Field::Latency => write!(out, "{:?}", record.latency).unwrap(),
+            Field::LatencyMs => write!(out, "{:.3}", latency_ms(record.latency)).unwrap(),
+            Field::Size => or_dash(out, record.response_size),
+            Field::RemoteAddr => or_dash(out, record.remote_addr),
+            Field::RemoteIp => or_dash(out, record.remote_addr.map(|addr| addr.ip())),
+            Field::UserAgent => or_dash(out, record.user_agent),
+            Field::Referer => or_dash(out, record.referer),
+            Field::RequestId => or_dash(out, record.request_id),
+            Field::Time => write_clf_time(out, record.time),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidFormat {
+    #[error("unknown field: {0}")]
+    UnknownField(String),
+    #[error("unterminated field")]
+    Unterminated,
+}
+
+impl Default for LogFormat {
+    /// `GET /path -> 200 OK (1.234ms)`
+    fn default() -> Self {
+        LogFormat(FormatKind::Default)
+    }
+}
+
+impl LogFormat {
+    /// The Apache common log format.
+    pub fn common() -> Self {
+        LogFormat(FormatKind::Common)
+    }
+
+    /// The Apache combined log format, which adds the referer and user agent to the
+    /// common log format.
+    pub fn combined() -> Self {
+        LogFormat(FormatKind::Combined)
+    }
+
+    /// A single line JSON object per request.
+    pub fn json() -> Self {
+        LogFormat(FormatKind::Json)
+    }
+
+    /// A custom format, where fields are written as `{name}` and literal braces as
+    /// `{{` and `}}`.
+    ///
+    /// The available fields are `method`, `uri`, `path`, `query`, `version`,
+    /// `status`, `latency`, `latency_ms`, `size`, `remote_addr`, `remote_ip`,
+    /// `user_agent`, `referer`, `request_id` and `time`. Missing values are written
+    /// as `-`.
+    pub fn custom(format: &str) -> Result<Self, InvalidFormat> {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = format.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(InvalidFormat::Unterminated),
+                        }
+                    }
+
+                    let field =
+                        Field::from_name(name.trim()).ok_or(InvalidFormat::UnknownField(name))?;
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    segments.push(Segment::Field(field));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(LogFormat(FormatKind::Custom(segments)))
+    }
+
+    pub fn format(&self, record: &LogRecord) -> String {
+        let mut out = String::new();
+
+        match &self.0 {
+            FormatKind::Default => {
+                write!(out, "{} {} -> ", record.method, record.uri).unwrap();
+                match record.status {
+                    Some(status) => write!(out, "{}", status).unwrap(),
+                    None => out.push_str("no response"),
+                }
+                write!(out, " ({:?})", record.latency).unwrap();
+            }
+            FormatKind::Common => write_common(&mut out, record),
+            FormatKind::Combined => {
+                write_common(&mut out, record);
+                out.push(' ');
+                write_quoted(&mut out, record.referer);
+                out.push(' ');
+                write_quoted(&mut out, record.user_agent);
+            }
+            FormatKind::Json => {
+                let json = serde_json::json!({
+                    "time": record.time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+                    "method": record.method.as_str(),
+                    "uri": record.uri.to_string(),
+                    "version": format!("{:?}", record.version),
+                    "status": record.status.map(|s| s.as_u16()),
+                    "latency_ms": latency_ms(record.latency),
+                    "size": record.response_size,
+                    "remote_addr": record.remote_addr.map(|addr| addr.to_string()),
+                    "user_agent": record.user_agent,
+                    "referer": record.referer,
+                    "request_id": record.request_id,
+                });
+                out = json.to_string();
+            }
+            FormatKind::Custom(segments) => {
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(s) => out.push_str(s),
+                        Segment::Field(field) => field.write(&mut out, record),
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326`
+fn write_common(out: &mut String, record: &LogRecord) {
+    Field::RemoteIp.write(out, record);
+    out.push_str(" - - [");
+    Field::Time.write(out, record);
+    out.push_str("] \"");
+    Field::Method.write(out, record);
+    out.push(' ');
+    Field::Uri.write(out, record);
+    out.push(' ');
+    Field::Version.write(out, record);
+    out.push_str("\" ");
+    Field::Status.write(out, record);
+    out.push(' ');
+    Field::Size.write(out, record);
+}
+
+/// Writes a value in quotes, escaped as Apache does so that clients can't break out
+/// of the quotes or forge log lines.
+fn write_quoted(out: &mut String, val: Option<&str>) {
+    out.push('"');
+
+    match val {
+        Some(val) => {
+            for c in val.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c if c.is_ascii_control() => write!(out, "\\x{:02x}", c as u32).unwrap(),
+                    c => out.push(c),
+                }
+            }
+        }
+        None => out.push('-'),
+    }
+
+    out.push('"');
+}
+
+fn latency_ms(latency: Duration) -> f64 {
+    latency.as_secs_f64() * 1000.0
+}
+
+/// Writes a UTC timestamp in the format used by the common log format.
+fn write_clf_time(out: &mut String, time: SystemTime) {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    write!(
+        out,
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+    .unwrap();
+}
 
 #[async_trait]
 impl Handler for Logger {
     async fn run(&self, req: Request, next: &dyn Next) -> Request {
+        let time = SystemTime::now();
+        let start = Instant::now();
+
         let req = next.run(req).await;
-        let status = req.res().map(|res| res.status());
-
-        match status {
-            None => log::error!("{} {} -> no response", req.method(), req.uri()),
-            Some(status) => {
-                if status.is_client_error() {
-                    log::warn!("{} {} -> {}", req.method(), req.uri(), status);
-                } else if status.is_server_error() {
-                    log::error!("{} {} -> {}", req.method(), req.uri(), status);
-                } else {
-                    log::info!("{} {} -> {}", req.method(), req.uri(), status);
-                }
-            }
+
+        if self.excluded_paths.contains(req.uri().path()) {
+            return req;
+        }
+
+        let record = LogRecord::from_request(&req, time, start.elapsed());
+        let always_log = record.status.is_none_or(|status| status.is_server_error());
+
+        if always_log || self.is_sampled() {
+            let line = self.format.format(&record);
+            self.sink.log(&record, &line);
         }
 
         req
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    /// A record of a request from `192.0.2.1:4000` that was answered in 1.5ms, a
+    /// second after the epoch.
+    fn record<'a>(method: &'a Method, uri: &'a Uri) -> LogRecord<'a> {
+        LogRecord {
+            method,
+            uri,
+            version: Version::HTTP_11,
+            status: Some(StatusCode::OK),
+            latency: Duration::from_micros(1500),
+            response_size: Some(2),
+            remote_addr: Some(SocketAddr::from(([192, 0, 2, 1], 4000))),
+            user_agent: Some("curl"),
+            referer: None,
+            request_id: Some("abc"),
+            time: UNIX_EPOCH + Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn combined_format_escapes_quoted_fields() {
+        let method = Method::GET;
+        let uri = Uri::from_static("/");
+        let record = LogRecord {
+            remote_addr: None,
+            user_agent: Some("a\" \\b\tc\x01"),
+            time: UNIX_EPOCH,
+            ..record(&method, &uri)
+        };
+
+        assert_eq!(
+            LogFormat::combined().format(&record),
+            r#"- - - [01/Jan/1970:00:00:00 +0000] "GET / HTTP/1.1" 200 2 "-" "a\" \\b\tc\x01""#
+        );
+    }
+
+    #[test]
+    fn custom_format() {
+        let method = Method::POST;
+        let uri = Uri::from_static("/users?page=2");
+        let record = record(&method, &uri);
+
+        let format = LogFormat::custom(
+            "{remote_ip} [{time}] {method} {path} {query} { status } {size} {latency_ms}ms \
+             {{{request_id}}} {referer} {user_agent} a}b",
+        )
+        .unwrap();
+
+        assert_eq!(
+            format.format(&record),
+            "192.0.2.1 [01/Jan/1970:00:00:01 +0000] POST /users page=2 200 2 1.500ms \
+             {abc} - curl a}b"
+        );
+
+        let format = LogFormat::custom("{uri} {version} {remote_addr} {latency}").unwrap();
+        assert_eq!(
+            format.format(&record),
+            "/users?page=2 HTTP/1.1 192.0.2.1:4000 1.5ms"
+        );
+    }
+
+    #[test]
+    fn invalid_custom_format() {
+        assert!(matches!(
+            LogFormat::custom("{status} {nope}"),
+            Err(InvalidFormat::UnknownField(name)) if name == "nope"
+        ));
+        assert!(matches!(
+            LogFormat::custom("{status"),
+            Err(InvalidFormat::Unterminated)
+        ));
+    }
+
+    #[test]
+    fn json_format() {
+        let method = Method::GET;
+        let uri = Uri::from_static("/users?page=2");
+        let record = LogRecord {
+            status: None,
+            ..record(&method, &uri)
+        };
+
+        let json: serde_json::Value =
+            serde_json::from_str(&LogFormat::json().format(&record)).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "time": 1000,
+                "method": "GET",
+                "uri": "/users?page=2",
+                "version": "HTTP/1.1",
+                "status": null,
+                "latency_ms": 1.5,
+                "size": 2,
+                "remote_addr": "192.0.2.1:4000",
+                "user_agent": "curl",
+                "referer": null,
+                "request_id": "abc",
+            })
+        );
+    }
+
+    /// Sends requests for each of the paths through `logger`, to an endpoint that
+    /// responds with the status in the path (e.g. `/500`), or not at all for other
+    /// paths. Returns the logged lines.
+    async fn log(logger: Logger, paths: &[&str]) -> Vec<String> {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let sink = {
+            let lines = lines.clone();
+            move |_: &LogRecord, line: &str| lines.lock().unwrap().push(line.to_owned())
+        };
+        let logger = logger
+            .with_format(LogFormat::custom("{path} {status}").unwrap())
+            .with_sink(sink);
+
+        let endpoint = NextFn(|mut req: Request| async move {
+            if let Ok(status) = req.uri().path()[1..].parse::<u16>() {
+                req.set_res(StatusCode::from_u16(status).unwrap());
+            }
+            req
+        });
+
+        for path in paths {
+            let req = hyper::Request::get(*path).body(Body::empty()).unwrap();
+            logger.run(Request::new(req, None), &endpoint).await;
+        }
+
+        let lines = lines.lock().unwrap().clone();
+        lines
+    }
+
+    #[tokio::test]
+    async fn always_logs_server_errors() {
+        let logger = Logger::new().with_sample_rate(0.0);
+        let lines = log(logger, &["/200", "/404", "/500", "/503", "/none"]).await;
+
+        assert_eq!(lines, ["/500 500", "/503 503", "/none -"]);
+    }
+
+    #[tokio::test]
+    async fn samples_requests() {
+        let logger = Logger::new().with_sample_rate(0.25);
+        let lines = log(logger, &["/200"; 1000]).await;
+
+        assert!((150..350).contains(&lines.len()), "{}", lines.len());
+    }
+
+    #[tokio::test]
+    async fn skips_excluded_paths() {
+        let logger = Logger::new().with_excluded_path("/200");
+        let lines = log(logger, &["/200", "/201", "/200?full=1"]).await;
+
+        assert_eq!(lines, ["/201 201"]);
+    }
+}
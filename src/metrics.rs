@@ -0,0 +1,408 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use headers::ContentType;
+use hyper::Method;
+
+use crate::router::RouterRequestExt;
+use crate::{Handler, Next, Request, Response};
+
+const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Middleware that records Prometheus style metrics for every request.
+///
+/// Requests are labelled by method, matched route pattern (or `unmatched` if no
+/// route matched) and status class. The number of requests in flight is only
+/// labelled by method, since the route is not known until a router has run.
+/// Methods other than the standard ones are labelled `OTHER`, so clients can't
+/// create arbitrarily many series.
+///
+/// Use [`Metrics::exporter`] to get a handler that serves the collected metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+}
+
+struct Registry {
+    buckets: Vec<f64>,
+    requests: Mutex<BTreeMap<RequestLabels, RequestSeries>>,
+    in_flight: Mutex<BTreeMap<&'static str, Arc<AtomicI64>>>,
+    custom: Mutex<Vec<CustomMetric>>,
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestLabels {
+    method: &'static str,
+    route: String,
+    status: &'static str,
+}
+
+struct RequestSeries {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+struct CustomMetric {
+    name: String,
+    help: String,
+    kind: &'static str,
+    value: Arc<AtomicI64>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::with_buckets(DEFAULT_BUCKETS.to_vec())
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Creates metrics using the given upper bounds, in seconds, for the request
+    /// duration histogram.
+    pub fn with_buckets(mut buckets: Vec<f64>) -> Self {
+        buckets.retain(|b| b.is_finite());
+        buckets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        buckets.dedup();
+
+        Metrics {
+            registry: Arc::new(Registry {
+                buckets,
+                requests: Mutex::new(BTreeMap::new()),
+                in_flight: Mutex::new(BTreeMap::new()),
+                custom: Mutex::new(vec![]),
+            }),
+        }
+    }
+
+    /// Returns a handler that responds with the collected metrics in the Prometheus
+    /// text exposition format.
+    pub fn exporter(&self) -> MetricsExporter {
+        MetricsExporter {
+            registry: self.registry.clone(),
+        }
+    }
+
+    /// Registers a counter that is exported along with the request metrics.
+    pub fn counter(&self, name: impl Into<String>, help: impl Into<String>) -> Counter {
+        Counter(self.register(name.into(), help.into(), "counter"))
+    }
+
+    /// Registers a gauge that is exported along with the request metrics.
+    pub fn gauge(&self, name: impl Into<String>, help: impl Into<String>) -> Gauge {
+        Gauge(self.register(name.into(), help.into(), "gauge"))
+    }
+
+    fn register(&self, name: String, help: String, kind: &'static str) -> Arc<AtomicI64> {
+        let mut custom = self.registry.custom.lock().unwrap();
+
+        if let Some(metric) = custom.iter().find(|m| m.name == name) {
+            return metric.value.clone();
+        }
+
+        let value = Arc::new(AtomicI64::new(0));
+        custom.push(CustomMetric {
+            name,
+            help,
+            kind,
+            value: value.clone(),
+        });
+        value
+    }
+}
+
+/// A monotonically increasing metric.
+#[derive(Clone)]
+pub struct Counter(Arc<AtomicI64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n as i64, Ordering::Relaxed);
+    }
+}
+
+/// A metric that can go up and down.
+#[derive(Clone)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1);
+    }
+
+    pub fn add(&self, n: i64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, n: i64) {
+        self.0.store(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements the in-flight gauge when dropped, so that it stays accurate even if
+/// the request future is cancelled.
+struct InFlightGuard(Arc<AtomicI64>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The label for a request method.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::CONNECT => "CONNECT",
+        Method::DELETE => "DELETE",
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::PATCH => "PATCH",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::TRACE => "TRACE",
+        _ => "OTHER",
+    }
+}
+
+impl Registry {
+    fn in_flight(&self, method: &Method) -> InFlightGuard {
+        let gauge = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(method_label(method))
+            .or_default()
+            .clone();
+
+        gauge.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(gauge)
+    }
+
+    fn observe(&self, labels: RequestLabels, seconds: f64) {
+        let mut requests = self.requests.lock().unwrap();
+        let series = requests.entry(labels).or_insert_with(|| RequestSeries {
+            buckets: vec![0; self.buckets.len()],
+            sum: 0.0,
+            count: 0,
+        });
+
+        for (count, bound) in series.buckets.iter_mut().zip(&self.buckets) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+
+        series.sum += seconds;
+        series.count += 1;
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        let requests = self.requests.lock().unwrap();
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "Total number of HTTP requests.",
+            "counter",
+        );
+        for (labels, series) in requests.iter() {
+            writeln!(out, "http_requests_total{{{}}} {}", labels, series.count).unwrap();
+        }
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "HTTP request latency in seconds.",
+            "histogram",
+        );
+        for (labels, series) in requests.iter() {
+            let name = "http_request_duration_seconds";
+            for (count, bound) in series.buckets.iter().zip(&self.buckets) {
+                writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name, labels, bound, count
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "{}_bucket{{{},le=\"+Inf\"}} {}",
+                name, labels, series.count
+            )
+            .unwrap();
+            writeln!(out, "{}_sum{{{}}} {}", name, labels, series.sum).unwrap();
+            writeln!(out, "{}_count{{{}}} {}", name, labels, series.count).unwrap();
+        }
+
+        drop(requests);
+
+        header(
+            &mut out,
+            "http_requests_in_flight",
+            "Number of HTTP requests currently being handled.",
+            "gauge",
+        );
+        for (method, gauge) in self.in_flight.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "http_requests_in_flight{{method=\"{}\"}} {}",
+                escape(method),
+                gauge.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+
+        for metric in self.custom.lock().unwrap().iter() {
+            header(&mut out, &metric.name, &metric.help, metric.kind);
+            writeln!(
+                out,
+                "{} {}",
+                metric.name,
+                metric.value.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+impl std::fmt::Display for RequestLabels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "method=\"{}\",route=\"{}\",status=\"{}\"",
+            escape(self.method),
+            escape(&self.route),
+            self.status
+        )
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(
+        out,
+        "# HELP {} {}",
+        name,
+        help.replace('\\', "\\\\").replace('\n', "\\n")
+    )
+    .unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn status_class(req: &Request) -> &'static str {
+    match req.res().map(|res| res.status().as_u16() / 100) {
+        Some(1) => "1xx",
+        Some(2) => "2xx",
+        Some(3) => "3xx",
+        Some(4) => "4xx",
+        Some(5) => "5xx",
+        _ => "none",
+    }
+}
+
+#[async_trait]
+impl Handler for Metrics {
+    async fn run(&self, req: Request, next: &dyn Next) -> Request {
+        let start = Instant::now();
+        let method = req.method().clone();
+        let guard = self.registry.in_flight(&method);
+
+        let req = next.run(req).await;
+
+        drop(guard);
+
+        let labels = RequestLabels {
+            method: method_label(&method),
+            route: req.route_pattern().unwrap_or("unmatched").to_owned(),
+            status: status_class(&req),
+        };
+
+        self.registry.observe(labels, start.elapsed().as_secs_f64());
+
+        req
+    }
+}
+
+/// Serves metrics collected by [`Metrics`]. See [`Metrics::exporter`].
+#[derive(Clone)]
+pub struct MetricsExporter {
+    registry: Arc<Registry>,
+}
+
+#[async_trait]
+impl Handler for MetricsExporter {
+    async fn run(&self, mut req: Request, _: &dyn Next) -> Request {
+        let content_type = "text/plain; version=0.0.4; charset=utf-8"
+            .parse::<mime::Mime>()
+            .unwrap();
+
+        req.set_res(
+            Response::ok()
+                .with_header(ContentType::from(content_type))
+                .with_body(self.registry.render()),
+        );
+
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::NextFn;
+
+    #[tokio::test]
+    async fn extension_methods_share_a_label() {
+        let metrics = Metrics::new();
+
+        for method in ["GET", "FOO", "BAR"] {
+            let req = hyper::Request::builder()
+                .method(method)
+                .body(hyper::Body::empty())
+                .unwrap();
+
+            metrics
+                .run(Request::new(req, None), &NextFn(|req| async move { req }))
+                .await;
+        }
+
+        let output = metrics.registry.render();
+
+        assert!(output.contains("method=\"GET\""));
+        assert!(output.contains("http_requests_in_flight{method=\"OTHER\"} 0"));
+        assert!(!output.contains("FOO") && !output.contains("BAR"));
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use hyper::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use hyper::{HeaderMap, StatusCode};
+
+use crate::auth::AuthRequestExt;
+use crate::{Handler, Next, Request, Response};
+
+/// How often the in-memory store drops keys that have fully recovered.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A rate limit, as a number of requests that can be made in a burst, and the rate at
+/// which that allowance recovers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    burst: u32,
+    interval: Duration,
+}
+
+impl Quota {
+    /// Allows `limit` requests per `period`, which can all be made at once. Rates
+    /// beyond one request per nanosecond are treated as one per nanosecond.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    pub fn new(limit: u32, period: Duration) -> Self {
+        assert!(limit > 0, "rate limit must be greater than zero");
+
+        Quota {
+            burst: limit,
+            interval: (period / limit).max(Duration::from_nanos(1)),
+        }
+    }
+
+    pub fn per_second(limit: u32) -> Self {
+        Quota::new(limit, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(limit: u32) -> Self {
+        Quota::new(limit, Duration::from_secs(60))
+    }
+
+    pub fn per_hour(limit: u32) -> Self {
+        Quota::new(limit, Duration::from_secs(3600))
+    }
+
+    /// Sets how many requests can be made at once, while keeping the same long term
+    /// rate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `burst` is zero.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        assert!(burst > 0, "burst must be greater than zero");
+        self.burst = burst;
+        self
+    }
+
+    /// The time it takes for a used up burst to fully recover.
+    pub fn window(&self) -> Duration {
+        self.interval * self.burst
+    }
+
+    /// Applies the
+    /// [generic cell rate algorithm](https://en.wikipedia.org/wiki/Generic_cell_rate_algorithm)
+    /// to a request made at `now`, given the theoretical arrival time stored for its
+    /// key. Returns the decision and the new theoretical arrival time to store.
+    ///
+    /// This is for implementing [`RateLimitStore`]s.
+    pub fn apply(&self, tat: Option<SystemTime>, now: SystemTime) -> (Decision, SystemTime) {
+        let tat = tat.filter(|tat| *tat > now).unwrap_or(now);
+        let new_tat = tat + self.interval;
+        let allow_at = new_tat - self.window();
+
+        let until = |time: SystemTime| time.duration_since(now).unwrap_or_default();
+
+        match now.duration_since(allow_at) {
+            Ok(spare) => {
+                let decision = Decision {
+                    allowed: true,
+                    limit: self.burst,
+                    remaining: (spare.as_nanos() / self.interval.as_nanos().max(1)) as u32,
+                    reset: until(new_tat),
+                    retry_after: None,
+                };
+
+                (decision, new_tat)
+            }
+            Err(_) => {
+                let decision = Decision {
+                    allowed: false,
+                    limit: self.burst,
+                    remaining: 0,
+                    reset: until(tat),
+                    retry_after: Some(until(allow_at)),
+                };
+
+                (decision, tat)
+            }
+        }
+    }
+}
+
+/// The outcome of checking a request against a [`Quota`].
+#[derive(Clone, Debug)]
+pub struct Decision {
+    pub allowed: bool,
+    /// The number of requests that can be made in a burst.
+    pub limit: u32,
+    /// The number of requests that can still be made right now.
+    pub remaining: u32,
+    /// The time until the full burst is available again.
+    pub reset: Duration,
+    /// For denied requests, the time until a request will be allowed.
+    pub retry_after: Option<Duration>,
+}
+
+/// Keeps track of rate limit state for each key.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync + 'static {
+    /// Checks a request against the quota and records it if it is allowed. This must
+    /// be atomic for each key, which stores can implement using [`Quota::apply`].
+    async fn check(
+        &self,
+        key: &str,
+        quota: &Quota,
+    ) -> Result<Decision, Box<dyn Error + Send + Sync>>;
+}
+
+/// Keeps rate limit state in memory. Keys are dropped once they have fully recovered,
+/// so memory use is bounded by the number of recently active clients.
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+struct MemoryState {
+    tats: HashMap<String, SystemTime>,
+    evicted_at: SystemTime,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore {
+            state: Mutex::new(MemoryState {
+                tats: HashMap::new(),
+                evicted_at: SystemTime::now(),
+            }),
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn check(
+        &self,
+        key: &str,
+        quota: &Quota,
+    ) -> Result<Decision, Box<dyn Error + Send + Sync>> {
+        let now = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+
+        if now
+            .duration_since(state.evicted_at)
+            .is_ok_and(|elapsed| elapsed >= EVICTION_INTERVAL)
+        {
+            state.tats.retain(|_, tat| *tat > now);
+            state.evicted_at = now;
+        }
+
+        let (decision, tat) = quota.apply(state.tats.get(key).copied(), now);
+        state.tats.insert(key.to_owned(), tat);
+
+        Ok(decision)
+    }
+}
+
+type KeyFn = Box<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+/// Middleware that limits the rate of requests, responding with
+/// `429 Too Many Requests` when the limit is exceeded.
+///
+/// Requests are keyed by the client's IP address by default. Responses include
+/// `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and `RateLimit-Policy`
+/// headers, and `Retry-After` if the request was limited.
+///
+/// Each `RateLimit` has its own state, so different limits can be applied to
+/// different routes by adding one to each. If the store fails, the error is logged
+/// and requests are let through.
+pub struct RateLimit {
+    quota: Quota,
+    key: KeyFn,
+    store: Box<dyn RateLimitStore>,
+}
+
+impl RateLimit {
+    pub fn new(quota: Quota) -> Self {
+        RateLimit {
+            quota,
+            key: Box::new(ip_key),
+            store: Box::new(MemoryStore::new()),
+        }
+    }
+
+    /// Keys requests by the client's IP address. This is the default.
+    pub fn by_ip(mut self) -> Self {
+        self.key = Box::new(ip_key);
+        self
+    }
+
+    /// Keys requests by the principal they were authenticated as (see
+    /// [`AuthRequestExt::principal`]), using the given function to identify it.
+    /// Anonymous requests are keyed by IP address.
+    pub fn by_principal<P: Send + Sync + 'static>(
+        mut self,
+        id: impl Fn(&P) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.key = Box::new(move |req| match req.principal::<P>() {
+            Some(principal) => Some(format!("principal:{}", id(principal))),
+            None => ip_key(req),
+        });
+        self
+    }
+
+    /// Keys requests with the given function. Requests for which it returns `None`
+    /// are not limited.
+    pub fn by_key(
+        mut self,
+        key: impl Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.key = Box::new(key);
+        self
+    }
+
+    /// Sets the store that rate limit state is kept in, e.g. to share it between
+    /// servers. Defaults to a [`MemoryStore`].
+    pub fn with_store(mut self, store: impl RateLimitStore) -> Self {
+        self.store = Box::new(store);
+        self
+    }
+
+    fn set_headers(&self, headers: &mut HeaderMap, decision: &Decision) {
+        let mut set = |name: &'static str, value: String| {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        };
+
+        set("ratelimit-limit", decision.limit.to_string());
+        set("ratelimit-remaining", decision.remaining.to_string());
+        set("ratelimit-reset", ceil_secs(decision.reset).to_string());
+        set(
+            "ratelimit-policy",
+            format!("{};w={}", self.quota.burst, ceil_secs(self.quota.window())),
+        );
+
+        if let Some(retry_after) = decision.retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from(ceil_secs(retry_after)));
+        }
+    }
+}
+
+fn ip_key(req: &Request) -> Option<String> {
+    req.remote_addr().map(|addr| format!("ip:{}", addr.ip()))
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[async_trait]
+impl Handler for RateLimit {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let key = match (self.key)(&req) {
+            Some(key) => key,
+            None => return next.run(req).await,
+        };
+
+        let decision = match self.store.check(&key, &self.quota).await {
+            Ok(decision) => decision,
+            Err(err) => {
+                log::error!("failed to check rate limit: {}", err);
+                return next.run(req).await;
+            }
+        };
+
+        if !decision.allowed {
+            let mut res = Response::from(StatusCode::TOO_MANY_REQUESTS);
+            self.set_headers(res.headers_mut(), &decision);
+            req.set_res(res);
+            return req;
+        }
+
+        let mut req = next.run(req).await;
+
+        if let Some(res) = req.res_mut() {
+            self.set_headers(res.headers_mut(), &decision);
+        }
+
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::NextFn;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    /// Applies the quota to requests made at each of the given times, in seconds
+    /// since the epoch, returning the decisions.
+    fn apply(quota: Quota, times: &[u64]) -> Vec<Decision> {
+        let mut tat = None;
+
+        times
+            .iter()
+            .map(|&time| {
+                let (decision, new_tat) = quota.apply(tat, SystemTime::UNIX_EPOCH + secs(time));
+                tat = Some(new_tat);
+                decision
+            })
+            .collect()
+    }
+
+    #[test]
+    fn allows_burst_then_denies() {
+        let decisions = apply(Quota::new(3, secs(3)), &[0, 0, 0, 0, 1, 1]);
+
+        let allowed: Vec<_> = decisions.iter().map(|d| d.allowed).collect();
+        let remaining: Vec<_> = decisions.iter().map(|d| d.remaining).collect();
+        assert_eq!(allowed, [true, true, true, false, true, false]);
+        assert_eq!(remaining, [2, 1, 0, 0, 0, 0]);
+
+        assert_eq!(decisions[0].reset, secs(1));
+        assert_eq!(decisions[2].reset, secs(3));
+        assert_eq!(decisions[2].retry_after, None);
+        assert_eq!(decisions[3].retry_after, Some(secs(1)));
+        assert_eq!(decisions[3].reset, secs(3));
+    }
+
+    #[test]
+    fn burst_keeps_rate() {
+        let quota = Quota::per_minute(60).with_burst(10);
+        assert_eq!(quota.window(), secs(10));
+
+        let decisions = apply(quota, &[0; 11]);
+        assert!(decisions[..10].iter().all(|d| d.allowed));
+        assert!(!decisions[10].allowed);
+        assert_eq!(decisions[10].retry_after, Some(secs(1)));
+
+        // It fully recovers over the window.
+        let mut times = vec![0; 10];
+        times.extend([10; 10]);
+        assert!(apply(quota, &times).iter().all(|d| d.allowed));
+    }
+
+    #[test]
+    fn clamps_interval() {
+        let quota = Quota::new(u32::MAX, Duration::from_millis(1));
+        assert_eq!(quota.interval, Duration::from_nanos(1));
+
+        let decisions = apply(quota, &[0, 0]);
+        assert!(decisions.iter().all(|d| d.allowed));
+        assert_eq!(decisions[1].remaining, u32::MAX - 2);
+    }
+
+    struct FailingStore;
+
+    #[async_trait]
+    impl RateLimitStore for FailingStore {
+        async fn check(
+            &self,
+            _: &str,
+            _: &Quota,
+        ) -> Result<Decision, Box<dyn Error + Send + Sync>> {
+            Err("unavailable".into())
+        }
+    }
+
+    /// Sends a request from the given IP address through the rate limit to an
+    /// endpoint that responds with `200 OK`.
+    async fn request(rate_limit: &RateLimit, ip: [u8; 4]) -> Response {
+        let req = hyper::Request::get("/").body(Body::empty()).unwrap();
+        let req = Request::new(req, Some(SocketAddr::from((ip, 1234))));
+
+        let endpoint = NextFn(|mut req: Request| async move {
+            req.set_res(StatusCode::OK);
+            req
+        });
+
+        rate_limit.run(req, &endpoint).await.take_res().unwrap()
+    }
+
+    #[tokio::test]
+    async fn sets_headers() {
+        let rate_limit = RateLimit::new(Quota::per_minute(2));
+
+        let res = request(&rate_limit, [1, 2, 3, 4]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["ratelimit-limit"], "2");
+        assert_eq!(res.headers()["ratelimit-remaining"], "1");
+        assert_eq!(res.headers()["ratelimit-reset"], "30");
+        assert_eq!(res.headers()["ratelimit-policy"], "2;w=60");
+        assert!(res.headers().get(RETRY_AFTER).is_none());
+
+        request(&rate_limit, [1, 2, 3, 4]).await;
+        let res = request(&rate_limit, [1, 2, 3, 4]).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["ratelimit-remaining"], "0");
+        assert_eq!(res.headers()["ratelimit-reset"], "60");
+        assert_eq!(res.headers()[RETRY_AFTER], "30");
+    }
+
+    #[tokio::test]
+    async fn isolates_keys() {
+        let rate_limit = RateLimit::new(Quota::per_minute(1));
+
+        assert_eq!(
+            request(&rate_limit, [1, 2, 3, 4]).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            request(&rate_limit, [1, 2, 3, 4]).await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            request(&rate_limit, [5, 6, 7, 8]).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn store_errors_fail_open() {
+        let rate_limit = RateLimit::new(Quota::per_minute(1)).with_store(FailingStore);
+
+        for _ in 0..3 {
+            let res = request(&rate_limit, [1, 2, 3, 4]).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(res.headers().get("ratelimit-limit").is_none());
+        }
+    }
+}
@@ -1,19 +1,62 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
 use headers::{Header, HeaderMapExt};
 use hyper::body::Buf;
-use hyper::{Body, HeaderMap, Method, Uri};
+use hyper::{Body, HeaderMap, Method, Uri, Version};
 use serde::de::DeserializeOwned;
 
 use crate::Response;
 
+/// An error reading a JSON request body with [`Request::body_json`].
+#[derive(Debug, thiserror::Error)]
+pub enum JsonBodyError {
+    #[error("failed to read body: {0}")]
+    Body(#[from] hyper::Error),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Copies an extension of one type from one request to another.
+type CloneExt = fn(&Request, &mut Request);
+
 #[derive(Debug)]
 pub struct Request {
     inner: hyper::Request<Body>,
+    remote_addr: Option<SocketAddr>,
     res: Option<Response>,
+    cloneable: HashMap<TypeId, CloneExt>,
 }
 
 impl Request {
-    pub(crate) fn new(inner: hyper::Request<Body>) -> Self {
-        Request { inner, res: None }
+    pub(crate) fn new(inner: hyper::Request<Body>, remote_addr: Option<SocketAddr>) -> Self {
+        Request {
+            inner,
+            remote_addr,
+            res: None,
+            cloneable: HashMap::new(),
+        }
+    }
+
+    /// Creates a request with the same method, URI, version, headers and remote
+    /// address, and copies of the extensions set with [`Request::set_cloneable_ext`],
+    /// but no body, other extensions or response. Used to respond to requests whose
+    /// handling was abandoned, e.g. after a timeout.
+    pub(crate) fn clone_head(&self) -> Self {
+        let mut inner = hyper::Request::new(Body::empty());
+        *inner.method_mut() = self.inner.method().clone();
+        *inner.uri_mut() = self.inner.uri().clone();
+        *inner.version_mut() = self.inner.version();
+        *inner.headers_mut() = self.inner.headers().clone();
+
+        let mut head = Request::new(inner, self.remote_addr);
+
+        for clone in self.cloneable.values() {
+            clone(self, &mut head);
+        }
+
+        head
     }
 
     pub fn method(&self) -> &Method {
@@ -24,10 +67,23 @@ impl Request {
         self.inner.uri()
     }
 
+    pub fn version(&self) -> Version {
+        self.inner.version()
+    }
+
+    /// The address of the peer that sent the request, if known.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
     pub fn headers(&self) -> &HeaderMap {
         self.inner.headers()
     }
 
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.inner.headers_mut()
+    }
+
     pub fn header<H: Header>(&self) -> Option<H> {
         self.inner.headers().typed_get()
     }
@@ -36,19 +92,27 @@ impl Request {
         std::mem::take(self.inner.body_mut())
     }
 
+    pub fn set_body(&mut self, body: impl Into<Body>) {
+        *self.inner.body_mut() = body.into();
+    }
+
     pub async fn body_bytes(&mut self) -> Result<hyper::body::Bytes, hyper::Error> {
         hyper::body::to_bytes(self.body()).await
     }
 
-    pub async fn body_json<T: DeserializeOwned>(&mut self) -> serde_json::Result<T> {
-        let body = hyper::body::aggregate(self.body()).await.unwrap();
-        serde_json::from_reader(body.reader())
+    pub async fn body_json<T: DeserializeOwned>(&mut self) -> Result<T, JsonBodyError> {
+        let body = hyper::body::aggregate(self.body()).await?;
+        Ok(serde_json::from_reader(body.reader())?)
     }
 
     pub fn ext<T: Send + Sync + 'static>(&self) -> Option<&T> {
         self.inner.extensions().get()
     }
 
+    pub fn ext_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.inner.extensions_mut().get_mut()
+    }
+
     pub fn set_ext<T: Send + Sync + 'static>(&mut self, val: T) -> Option<T> {
         self.inner.extensions_mut().insert(val)
     }
@@ -57,6 +121,21 @@ impl Request {
         self.inner.extensions_mut().remove()
     }
 
+    /// Sets an extension that is kept in the copy of the request that middleware like
+    /// `Timeout` and `CatchPanic` respond with when they abandon a handler.
+    pub(crate) fn set_cloneable_ext<T: Clone + Send + Sync + 'static>(
+        &mut self,
+        val: T,
+    ) -> Option<T> {
+        self.cloneable.insert(TypeId::of::<T>(), |from, to| {
+            if let Some(val) = from.ext::<T>() {
+                to.set_cloneable_ext(val.clone());
+            }
+        });
+
+        self.set_ext(val)
+    }
+
     pub fn res(&self) -> Option<&Response> {
         self.res.as_ref()
     }
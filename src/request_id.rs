@@ -0,0 +1,251 @@
+use async_trait::async_trait;
+use hyper::header::{HeaderName, HeaderValue};
+
+use crate::{Handler, Next, Request};
+
+/// Middleware that assigns every request an ID, taken from an incoming header or
+/// freshly generated, and echoes it back on the response.
+///
+/// The ID can be retrieved by later handlers with
+/// [`RequestIdRequestExt::request_id`], and is included in [`Logger`] output.
+///
+/// [`Logger`]: crate::logger::Logger
+pub struct RequestId {
+    header: HeaderName,
+    generator: Box<dyn Fn() -> String + Send + Sync>,
+}
+
+#[derive(Clone)]
+struct Id(String);
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId {
+            header: HeaderName::from_static("x-request-id"),
+            generator: Box::new(|| uuid::Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+impl RequestId {
+    pub fn new() -> Self {
+        RequestId::default()
+    }
+
+    /// Sets the header the ID is read from and written to. Defaults to
+    /// `X-Request-Id`.
+    pub fn with_header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Sets the function used to generate IDs for requests that don't already have
+    /// one. Defaults to random UUIDs.
+    pub fn with_generator(
+        mut self,
+        generator: impl Fn() -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.generator = Box::new(generator);
+        self
+    }
+
+    /// Generates IDs as ULIDs, which are lexicographically sortable by time.
+    #[cfg(feature = "ulid")]
+    pub fn with_ulid(self) -> Self {
+        self.with_generator(|| ulid::Ulid::new().to_string())
+    }
+}
+
+/// Returns whether an incoming ID is reasonable to propagate, to avoid letting
+/// clients inject arbitrary data into logs.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[async_trait]
+impl Handler for RequestId {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let id = req
+            .headers()
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| is_valid_id(id))
+            .map(str::to_owned)
+            .unwrap_or_else(|| (self.generator)());
+
+        req.set_cloneable_ext(Id(id.clone()));
+
+        let mut req = next.run(req).await;
+
+        if let (Some(res), Ok(value)) = (req.res_mut(), HeaderValue::from_str(&id)) {
+            res.headers_mut().insert(self.header.clone(), value);
+        }
+
+        req
+    }
+}
+
+pub trait RequestIdRequestExt {
+    fn request_id(&self) -> Option<&str>;
+}
+
+impl RequestIdRequestExt for Request {
+    fn request_id(&self) -> Option<&str> {
+        self.ext::<Id>().map(|id| id.0.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyper::{Body, StatusCode};
+
+    use super::*;
+    use crate::handler::NextFn;
+    use crate::logger::{LogFormat, LogRecord, Logger};
+
+    /// Sends a request with the given header through `request_id` to an endpoint,
+    /// returning the ID the endpoint saw and the response.
+    async fn run(
+        request_id: RequestId,
+        header: Option<(&str, HeaderValue)>,
+    ) -> (Option<String>, crate::Response) {
+        let mut req = hyper::Request::get("/");
+        if let Some((name, value)) = header {
+            req = req.header(name, value);
+        }
+        let req = Request::new(req.body(Body::empty()).unwrap(), None);
+
+        let seen = Arc::new(Mutex::new(None));
+        let endpoint = {
+            let seen = seen.clone();
+            NextFn(move |mut req: Request| {
+                *seen.lock().unwrap() = req.request_id().map(str::to_owned);
+                async move {
+                    req.set_res(StatusCode::OK);
+                    req
+                }
+            })
+        };
+
+        let mut req = request_id.run(req, &endpoint).await;
+        let seen = seen.lock().unwrap().take();
+        (seen, req.take_res().unwrap())
+    }
+
+    fn fixed() -> RequestId {
+        RequestId::new().with_generator(|| "generated".to_owned())
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_incoming_id() {
+        let id = "a".repeat(128);
+        let header = ("x-request-id", HeaderValue::from_str(&id).unwrap());
+
+        let (seen, res) = run(fixed(), Some(header)).await;
+
+        assert_eq!(seen.as_deref(), Some(id.as_str()));
+        assert_eq!(res.headers()["x-request-id"], id.as_str());
+    }
+
+    #[tokio::test]
+    async fn replaces_invalid_incoming_id() {
+        let invalid = [
+            HeaderValue::from_static(""),
+            HeaderValue::from_static("a b"),
+            HeaderValue::from_static("a\tb"),
+            HeaderValue::from_bytes(b"caf\xc3\xa9").unwrap(),
+            HeaderValue::from_str(&"a".repeat(129)).unwrap(),
+        ];
+
+        for value in invalid {
+            let (seen, res) = run(fixed(), Some(("x-request-id", value.clone()))).await;
+
+            assert_eq!(seen.as_deref(), Some("generated"), "{:?}", value);
+            assert_eq!(res.headers()["x-request-id"], "generated");
+        }
+    }
+
+    #[tokio::test]
+    async fn uses_configured_header() {
+        let request_id = || fixed().with_header(HeaderName::from_static("x-trace"));
+
+        let (seen, res) = run(
+            request_id(),
+            Some(("x-trace", HeaderValue::from_static("abc"))),
+        )
+        .await;
+        assert_eq!(seen.as_deref(), Some("abc"));
+        assert_eq!(res.headers()["x-trace"], "abc");
+        assert!(res.headers().get("x-request-id").is_none());
+
+        // Other headers are ignored.
+        let (seen, _) = run(
+            request_id(),
+            Some(("x-request-id", HeaderValue::from_static("abc"))),
+        )
+        .await;
+        assert_eq!(seen.as_deref(), Some("generated"));
+    }
+
+    #[tokio::test]
+    async fn generates_uuids_by_default() {
+        let (first, _) = run(RequestId::new(), None).await;
+        let (second, _) = run(RequestId::new(), None).await;
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        assert!(uuid::Uuid::parse_str(&first).is_ok());
+        assert_ne!(first, second);
+    }
+
+    #[cfg(feature = "ulid")]
+    #[tokio::test]
+    async fn generates_ulids() {
+        let (first, _) = run(RequestId::new().with_ulid(), None).await;
+        let (second, _) = run(RequestId::new().with_ulid(), None).await;
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        assert!(first.parse::<ulid::Ulid>().is_ok());
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn logs_id_from_configured_header() {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let sink = {
+            let lines = lines.clone();
+            move |_: &LogRecord, line: &str| lines.lock().unwrap().push(line.to_owned())
+        };
+        let logger = || {
+            Logger::new()
+                .with_format(LogFormat::custom("{request_id}").unwrap())
+                .with_sink(sink.clone())
+        };
+        let endpoint = |mut req: Request| async move {
+            req.set_res(StatusCode::OK);
+            req
+        };
+
+        let request = || {
+            let req = hyper::Request::get("/")
+                .header("x-trace", "abc")
+                .header("x-request-id", "unvalidated")
+                .body(Body::empty())
+                .unwrap();
+            Request::new(req, None)
+        };
+
+        let request_id = RequestId::new().with_header(HeaderName::from_static("x-trace"));
+        (logger(), (request_id, endpoint))
+            .run(request(), &NextFn(|req| async move { req }))
+            .await;
+
+        // Without `RequestId`, there is no ID to log.
+        (logger(), endpoint)
+            .run(request(), &NextFn(|req| async move { req }))
+            .await;
+
+        assert_eq!(*lines.lock().unwrap(), ["abc", "-"]);
+    }
+}
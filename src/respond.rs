@@ -7,17 +7,17 @@ use crate::{Request, Response};
 
 #[async_trait]
 pub trait RespondRequestExt {
-    fn ok(&mut self) -> Respond;
-    fn respond<R: Into<Response>>(&mut self, res: R) -> Respond;
+    fn ok(&mut self) -> Respond<'_>;
+    fn respond<R: Into<Response>>(&mut self, res: R) -> Respond<'_>;
 }
 
 #[async_trait]
 impl RespondRequestExt for Request {
-    fn ok(&mut self) -> Respond {
+    fn ok(&mut self) -> Respond<'_> {
         self.respond(StatusCode::OK)
     }
 
-    fn respond<R: Into<Response>>(&mut self, res: R) -> Respond {
+    fn respond<R: Into<Response>>(&mut self, res: R) -> Respond<'_> {
         Respond(self.set_res(res.into()))
     }
 }
@@ -1,17 +1,18 @@
+mod file;
+mod range;
+
 use std::convert::Infallible;
-use std::io::SeekFrom;
-use std::ops::Bound;
-use std::path::PathBuf;
+use std::error::Error as StdError;
 
-use headers::{AcceptRanges, ContentLength, ContentRange, ContentType, Range};
 use hyper::{Body, StatusCode};
 use serde::Serialize;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
-use tokio_util::codec::{BytesCodec, FramedRead};
 
+use crate::error::Error;
 use crate::respond::RespondRequestExt;
 use crate::{async_trait, Request, Response};
 
+pub use file::{CachePolicy, File};
+
 #[async_trait]
 pub trait Responder: Send {
     async fn respond_to(self, req: &mut Request);
@@ -67,9 +68,24 @@ impl Responder for Infallible {
 impl<T: Responder, E: Responder> Responder for Result<T, E> {
     async fn respond_to(self, req: &mut Request) {
         match self {
-            Ok(res) => res.respond_to(req),
-            Err(e) => e.respond_to(req),
-        };
+            Ok(res) => res.respond_to(req).await,
+            Err(e) => e.respond_to(req).await,
+        }
+    }
+}
+
+#[async_trait]
+impl Responder for Box<dyn StdError + Send + Sync> {
+    async fn respond_to(self, req: &mut Request) {
+        Error::internal(self).respond_to(req).await;
+    }
+}
+
+#[cfg(feature = "anyhow")]
+#[async_trait]
+impl Responder for anyhow::Error {
+    async fn respond_to(self, req: &mut Request) {
+        Error::internal(self).respond_to(req).await;
     }
 }
 
@@ -77,7 +93,7 @@ impl<T: Responder, E: Responder> Responder for Result<T, E> {
 #[async_trait]
 impl Responder for eyre::Error {
     async fn respond_to(self, req: &mut Request) {
-        req.set_ext(self);
+        Error::internal(self).respond_to(req).await;
     }
 }
 
@@ -87,82 +103,31 @@ pub struct Json<T>(pub T);
 impl<T: Serialize + Send> Responder for Json<T> {
     async fn respond_to(self, req: &mut Request) {
         if let Err(e) = req.ok().json(&self.0) {
-            req.set_res((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+            Error::from(e).respond_to(req).await;
         }
     }
 }
 
-pub struct File {
-    inner: tokio::fs::File,
-    mime: mime::Mime,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl File {
-    pub async fn open(path: impl Into<PathBuf>) -> std::io::Result<File> {
-        let path = path.into();
-        let mime = mime_guess::from_path(&path).first_or("video/*".parse().unwrap());
-        let file = tokio::fs::File::open(path).await?;
-        Ok(File { inner: file, mime })
+    fn request() -> Request {
+        Request::new(hyper::Request::new(Body::empty()), None)
     }
-}
 
-#[async_trait]
-impl Responder for File {
-    async fn respond_to(self, req: &mut Request) {
-        async fn respond_file(req: &mut Request, file: File) -> std::io::Result<Response> {
-            let File {
-                inner: mut file,
-                mime,
-            } = file;
-
-            let total_length = file.metadata().await?.len();
-            let range = req.header::<Range>().and_then(|range| range.iter().next());
-
-            let mut res = Response::ok()
-                .with_header(AcceptRanges::bytes())
-                .with_header(ContentType::from(mime));
-
-            match range {
-                Some((from, to)) => {
-                    let from = match from {
-                        Bound::Included(n) => n,
-                        Bound::Excluded(n) => n + 1,
-                        Bound::Unbounded => 0,
-                    };
-
-                    let to = match to {
-                        Bound::Included(n) => n,
-                        Bound::Excluded(n) => n - 1,
-                        Bound::Unbounded => total_length - 1,
-                    };
-
-                    file.seek(SeekFrom::Start(from)).await?;
-
-                    let read_length = u64::min(total_length - from, to - from + 1);
-                    let reader = file.take(read_length);
-                    let stream = FramedRead::new(reader, BytesCodec::new());
-                    let body = Body::wrap_stream(stream);
-                    let range = from..=from + read_length - 1;
-
-                    res.set_status(StatusCode::PARTIAL_CONTENT);
-                    res.set_header(ContentRange::bytes(range, total_length).unwrap());
-                    res.set_header(ContentLength(read_length));
-                    res.set_body(body);
-                }
-                None => {
-                    let stream = FramedRead::new(file, BytesCodec::new());
-                    let body = Body::wrap_stream(stream);
-
-                    res.set_header(ContentLength(total_length));
-                    res.set_body(body);
-                }
-            }
-
-            Ok(res)
-        }
-
-        if let Err(e) = respond_file(req, self).await {
-            req.set_res((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
-        }
+    #[tokio::test]
+    async fn result_responds_with_either_variant() {
+        let mut req = request();
+        Ok::<_, Infallible>(StatusCode::CREATED)
+            .respond_to(&mut req)
+            .await;
+        assert_eq!(req.res().map(Response::status), Some(StatusCode::CREATED));
+
+        let mut req = request();
+        Err::<Infallible, _>(StatusCode::NOT_FOUND)
+            .respond_to(&mut req)
+            .await;
+        assert_eq!(req.res().map(Response::status), Some(StatusCode::NOT_FOUND));
     }
 }
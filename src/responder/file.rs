@@ -0,0 +1,701 @@
+use std::collections::VecDeque;
+use std::fs::Metadata;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::stream::{self, Stream};
+use headers::{
+    AcceptRanges, CacheControl, ContentLength, ContentRange, ContentType, ETag, IfModifiedSince,
+    IfNoneMatch, IfRange, LastModified,
+};
+use hyper::body::Bytes;
+use hyper::header::{ACCEPT_ENCODING, CONTENT_ENCODING, RANGE};
+use hyper::{Body, Method, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use super::range::ByteRanges;
+use crate::encoding::{AcceptEncoding, Encoding};
+use crate::{async_trait, Request, Responder, Response};
+
+pub struct File {
+    inner: tokio::fs::File,
+    path: PathBuf,
+    mime: mime::Mime,
+    cache_control: Option<CacheControl>,
+    precompressed: Vec<Encoding>,
+}
+
+impl File {
+    pub async fn open(path: impl Into<PathBuf>) -> std::io::Result<File> {
+        let path = path.into();
+        let mime = mime_guess::from_path(&path).first_or("video/*".parse().unwrap());
+        let file = tokio::fs::File::open(&path).await?;
+        Ok(File {
+            inner: file,
+            path,
+            mime,
+            cache_control: None,
+            precompressed: vec![],
+        })
+    }
+
+    /// Sets the `Cache-Control` header that will be sent along with the file.
+    pub fn with_cache_control(mut self, cache_control: CacheControl) -> Self {
+        self.cache_control = Some(cache_control);
+        self
+    }
+
+    /// Sets the `Cache-Control` header from the policy of the directory the file is
+    /// in. Leaves it unchanged if the policy has nothing for the file.
+    pub fn with_cache_policy(mut self, policy: &CachePolicy) -> Self {
+        if let Some(cache_control) = policy.for_path(&self.path) {
+            self.cache_control = Some(cache_control.clone());
+        }
+
+        self
+    }
+
+    /// Enables serving precompressed siblings of the file (e.g. `style.css.br` for
+    /// `style.css`) to clients that accept the corresponding encoding.
+    ///
+    /// Encodings are tried in order of the client's preference, with ties broken by
+    /// the order given here. Encodings without a conventional file extension are
+    /// ignored.
+    pub fn with_precompressed(mut self, encodings: impl IntoIterator<Item = Encoding>) -> Self {
+        self.precompressed = encodings
+            .into_iter()
+            .filter(|encoding| encoding.extension().is_some())
+            .collect();
+        self
+    }
+}
+
+/// `Cache-Control` headers for files, chosen by the directory they are in, for use
+/// with [`File::with_cache_policy`].
+///
+/// Paths are compared component by component as they were given to [`File::open`],
+/// without resolving them, so directories should be given in the same form.
+#[derive(Clone, Debug, Default)]
+pub struct CachePolicy {
+    dirs: Vec<(PathBuf, CacheControl)>,
+    default: Option<CacheControl>,
+}
+
+impl CachePolicy {
+    pub fn new() -> Self {
+        CachePolicy::default()
+    }
+
+    /// Sets the header for files in `dir` and its subdirectories. Files in more than
+    /// one configured directory use the innermost one.
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>, cache_control: CacheControl) -> Self {
+        self.dirs.push((dir.into(), cache_control));
+        self
+    }
+
+    /// Sets the header for files that aren't in any configured directory.
+    pub fn with_default(mut self, cache_control: CacheControl) -> Self {
+        self.default = Some(cache_control);
+        self
+    }
+
+    /// The header for the file at `path`, if any.
+    pub fn for_path(&self, path: &Path) -> Option<&CacheControl> {
+        self.dirs
+            .iter()
+            .filter(|(dir, _)| path.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map(|(_, cache_control)| cache_control)
+            .or(self.default.as_ref())
+    }
+}
+
+/// Opens the best precompressed sibling of `path` accepted by the client, if any.
+async fn open_precompressed(
+    req: &Request,
+    path: &Path,
+    encodings: &[Encoding],
+) -> std::io::Result<Option<(tokio::fs::File, Encoding)>> {
+    let accept = match AcceptEncoding::from_headers(req.headers()) {
+        Some(accept) => accept,
+        None => return Ok(None),
+    };
+
+    let mut supported = encodings.to_vec();
+    supported.push(Encoding::Identity);
+
+    for encoding in accept.preferred(&supported) {
+        let extension = match encoding.extension() {
+            Some(extension) => extension,
+            None => break,
+        };
+
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(extension);
+
+        match tokio::fs::File::open(sibling).await {
+            Ok(file) => return Ok(Some((file, encoding))),
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Computes a strong validator for the file from its size and modification time.
+fn etag(metadata: &Metadata) -> Option<ETag> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    format!(
+        "\"{:x}-{:x}-{:x}\"",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len()
+    )
+    .parse()
+    .ok()
+}
+
+/// Evaluates `If-None-Match` and `If-Modified-Since` as described in
+/// [RFC 7232](https://tools.ietf.org/html/rfc7232#section-6), returning `true` if
+/// the client's cached copy is still fresh.
+fn is_not_modified(
+    req: &Request,
+    etag: Option<&ETag>,
+    last_modified: Option<&LastModified>,
+) -> bool {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return false;
+    }
+
+    // If-Modified-Since must be ignored if If-None-Match is present.
+    if let Some(if_none_match) = req.header::<IfNoneMatch>() {
+        return match etag {
+            Some(etag) => !if_none_match.precondition_passes(etag),
+            None => false,
+        };
+    }
+
+    match (req.header::<IfModifiedSince>(), last_modified) {
+        (Some(since), Some(&last_modified)) => !since.is_modified(last_modified.into()),
+        _ => false,
+    }
+}
+
+/// Size of the chunks read from the file when streaming a multipart body.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// A `multipart/byteranges` body, as described in
+/// [RFC 7233](https://tools.ietf.org/html/rfc7233#appendix-A).
+struct Multipart {
+    file: tokio::fs::File,
+    boundary: String,
+    parts: VecDeque<(Bytes, RangeInclusive<u64>)>,
+}
+
+impl Multipart {
+    fn new(
+        file: tokio::fs::File,
+        mime: &mime::Mime,
+        ranges: Vec<RangeInclusive<u64>>,
+        total_length: u64,
+    ) -> Multipart {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let boundary = format!(
+            "{:016x}{:08x}",
+            nanos,
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let parts = ranges
+            .into_iter()
+            .map(|range| {
+                let header = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary,
+                    mime,
+                    range.start(),
+                    range.end(),
+                    total_length
+                );
+                (Bytes::from(header), range)
+            })
+            .collect();
+
+        Multipart {
+            file,
+            boundary,
+            parts,
+        }
+    }
+
+    fn trailer(&self) -> Bytes {
+        Bytes::from(format!("\r\n--{}--\r\n", self.boundary))
+    }
+
+    fn content_length(&self) -> u64 {
+        let parts: u64 = self
+            .parts
+            .iter()
+            .map(|(header, range)| header.len() as u64 + range.end() - range.start() + 1)
+            .sum();
+
+        parts + self.trailer().len() as u64
+    }
+
+    fn into_stream(self) -> impl Stream<Item = std::io::Result<Bytes>> {
+        enum State {
+            Part(Multipart),
+            Reading(Multipart, u64),
+            Done,
+        }
+
+        stream::try_unfold(State::Part(self), |state| async move {
+            match state {
+                State::Part(mut multipart) => match multipart.parts.pop_front() {
+                    Some((header, range)) => {
+                        let length = range.end() - range.start() + 1;
+                        multipart.file.seek(SeekFrom::Start(*range.start())).await?;
+                        Ok(Some((header, State::Reading(multipart, length))))
+                    }
+                    None => Ok(Some((multipart.trailer(), State::Done))),
+                },
+                State::Reading(mut multipart, remaining) => {
+                    let mut buf = vec![0; CHUNK_SIZE.min(remaining) as usize];
+                    multipart.file.read_exact(&mut buf).await?;
+
+                    let remaining = remaining - buf.len() as u64;
+                    let next = if remaining == 0 {
+                        State::Part(multipart)
+                    } else {
+                        State::Reading(multipart, remaining)
+                    };
+
+                    Ok(Some((Bytes::from(buf), next)))
+                }
+                State::Done => Ok(None),
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl Responder for File {
+    async fn respond_to(self, req: &mut Request) {
+        async fn respond_file(req: &mut Request, file: File) -> std::io::Result<Response> {
+            let File {
+                inner: mut file,
+                path,
+                mime,
+                cache_control,
+                precompressed,
+            } = file;
+
+            let mut res = Response::ok();
+
+            // The precompressed file is a different representation of the same
+            // resource, so validators and ranges below all apply to it rather than
+            // to the original file.
+            if !precompressed.is_empty() {
+                res.add_vary(ACCEPT_ENCODING);
+
+                if let Some((encoded, encoding)) =
+                    open_precompressed(req, &path, &precompressed).await?
+                {
+                    file = encoded;
+                    res.headers_mut()
+                        .insert(CONTENT_ENCODING, encoding.to_header_value());
+                }
+            }
+
+            let metadata = file.metadata().await?;
+            let total_length = metadata.len();
+            let etag = etag(&metadata);
+            let last_modified = metadata.modified().ok().map(LastModified::from);
+
+            if let Some(etag) = &etag {
+                res.set_header(etag.clone());
+            }
+
+            if let Some(last_modified) = last_modified {
+                res.set_header(last_modified);
+            }
+
+            if let Some(cache_control) = cache_control {
+                res.set_header(cache_control);
+            }
+
+            if is_not_modified(req, etag.as_ref(), last_modified.as_ref()) {
+                res.set_status(StatusCode::NOT_MODIFIED);
+                return Ok(res);
+            }
+
+            // A range request is only honoured if the representation the client has
+            // is still current, otherwise the whole file must be sent.
+            let if_range_passes = match req.header::<IfRange>() {
+                Some(if_range) => !if_range.is_modified(etag.as_ref(), last_modified.as_ref()),
+                None => true,
+            };
+
+            let ranges = match req.headers().get(RANGE).and_then(|v| v.to_str().ok()) {
+                Some(range) if req.method() == Method::GET && if_range_passes => {
+                    ByteRanges::parse(range, total_length)
+                }
+                _ => ByteRanges::Full,
+            };
+
+            res.set_header(AcceptRanges::bytes());
+
+            match ranges {
+                ByteRanges::Full => {
+                    let stream = FramedRead::new(file, BytesCodec::new());
+                    let body = Body::wrap_stream(stream);
+
+                    res.set_header(ContentType::from(mime));
+                    res.set_header(ContentLength(total_length));
+                    res.set_body(body);
+                }
+                ByteRanges::Unsatisfiable => {
+                    res.set_status(StatusCode::RANGE_NOT_SATISFIABLE);
+                    res.set_header(ContentRange::unsatisfied_bytes(total_length));
+                    res.set_header(ContentLength(0));
+                }
+                ByteRanges::Satisfiable(ranges) if ranges.len() == 1 => {
+                    let range = ranges[0].clone();
+                    let read_length = range.end() - range.start() + 1;
+
+                    file.seek(SeekFrom::Start(*range.start())).await?;
+
+                    let reader = file.take(read_length);
+                    let stream = FramedRead::new(reader, BytesCodec::new());
+                    let body = Body::wrap_stream(stream);
+
+                    res.set_status(StatusCode::PARTIAL_CONTENT);
+                    res.set_header(ContentType::from(mime));
+                    res.set_header(ContentRange::bytes(range, total_length).unwrap());
+                    res.set_header(ContentLength(read_length));
+                    res.set_body(body);
+                }
+                ByteRanges::Satisfiable(ranges) => {
+                    let multipart = Multipart::new(file, &mime, ranges, total_length);
+                    let content_type =
+                        format!("multipart/byteranges; boundary={}", multipart.boundary);
+
+                    res.set_status(StatusCode::PARTIAL_CONTENT);
+                    res.set_header(ContentType::from(
+                        content_type.parse::<mime::Mime>().unwrap(),
+                    ));
+                    res.set_header(ContentLength(multipart.content_length()));
+                    res.set_body(Body::wrap_stream(multipart.into_stream()));
+                }
+            }
+
+            Ok(res)
+        }
+
+        match respond_file(req, self).await {
+            Ok(res) => req.set_res(res),
+            Err(e) => req.set_res((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, VARY};
+
+    use super::*;
+
+    /// Responds with the file to a GET request with the given headers.
+    async fn serve(file: File, headers: &[(&str, &str)]) -> (Response, Bytes) {
+        let mut req = hyper::Request::get("/");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let mut req = Request::new(req.body(Body::empty()).unwrap(), None);
+
+        file.respond_to(&mut req).await;
+
+        let mut res = req.take_res().unwrap();
+        let body = hyper::body::to_bytes(res.take_body()).await.unwrap();
+        (res, body)
+    }
+
+    /// Serves a 100 byte text file for a request with the given `Range` header.
+    async fn get_range(name: &str, range: &str) -> (Response, Bytes) {
+        let path = std::env::temp_dir().join(format!("atium-range-{}.txt", name));
+        let contents: Vec<u8> = (0..100).map(|i| b'a' + i % 26).collect();
+        tokio::fs::write(&path, &contents).await.unwrap();
+
+        let file = File::open(&path).await.unwrap();
+        let (res, body) = serve(file, &[("range", range)]).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        (res, body)
+    }
+
+    #[tokio::test]
+    async fn single_range() {
+        let (res, body) = get_range("single", "bytes=-4").await;
+
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes 96-99/100");
+        assert_eq!(&body[..], b"stuv");
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_range() {
+        let (res, body) = get_range("unsatisfiable", "bytes=100-").await;
+
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes */100");
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_range_serves_full_file() {
+        let (res, body) = get_range("invalid", "bytes=,").await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn multiple_ranges() {
+        let (res, body) = get_range("multiple", "bytes=0-2, 26-27").await;
+
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+
+        let content_type = res.headers()[CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+
+        let expected = format!(
+            "\r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-2/100\r\n\r\nabc\
+             \r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 26-27/100\r\n\r\nab\
+             \r\n--{0}--\r\n",
+            boundary
+        );
+
+        assert_eq!(std::str::from_utf8(&body).unwrap(), expected);
+        assert_eq!(res.headers()[CONTENT_LENGTH], body.len().to_string());
+    }
+
+    /// Writes a 100 byte file, returning its path and the `ETag` and `Last-Modified`
+    /// it's served with.
+    async fn conditional_file(name: &str) -> (PathBuf, String, String) {
+        let path = std::env::temp_dir().join(format!("atium-conditional-{}.txt", name));
+        tokio::fs::write(&path, [b'a'; 100]).await.unwrap();
+
+        let (res, _) = serve(File::open(&path).await.unwrap(), &[]).await;
+        let header = |name| res.headers()[name].to_str().unwrap().to_owned();
+
+        (path, header(ETAG), header(LAST_MODIFIED))
+    }
+
+    async fn status(path: &Path, headers: &[(&str, &str)]) -> (StatusCode, usize) {
+        let (res, body) = serve(File::open(path).await.unwrap(), headers).await;
+        (res.status(), body.len())
+    }
+
+    #[tokio::test]
+    async fn if_none_match() {
+        let (path, etag, last_modified) = conditional_file("if-none-match").await;
+        let weak = format!("W/{}", etag);
+        let list = format!("\"other\", {}", etag);
+
+        let (res, body) = serve(
+            File::open(&path).await.unwrap(),
+            &[("if-none-match", &etag)],
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()[ETAG], etag);
+        assert!(body.is_empty());
+
+        let not_modified = (StatusCode::NOT_MODIFIED, 0);
+        assert_eq!(status(&path, &[("if-none-match", "*")]).await, not_modified);
+        assert_eq!(
+            status(&path, &[("if-none-match", &weak)]).await,
+            not_modified
+        );
+        assert_eq!(
+            status(&path, &[("if-none-match", &list)]).await,
+            not_modified
+        );
+
+        let modified = (StatusCode::OK, 100);
+        let other = [("if-none-match", "\"other\"")];
+        assert_eq!(status(&path, &other).await, modified);
+
+        // If-Modified-Since is ignored when If-None-Match is present.
+        let headers = [
+            ("if-none-match", "\"other\""),
+            ("if-modified-since", &last_modified),
+        ];
+        assert_eq!(status(&path, &headers).await, modified);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn if_modified_since() {
+        let (path, _, last_modified) = conditional_file("if-modified-since").await;
+
+        let since = [("if-modified-since", last_modified.as_str())];
+        assert_eq!(status(&path, &since).await, (StatusCode::NOT_MODIFIED, 0));
+
+        let before = [("if-modified-since", "Thu, 01 Jan 2015 00:00:00 GMT")];
+        assert_eq!(status(&path, &before).await, (StatusCode::OK, 100));
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn if_range() {
+        let (path, etag, last_modified) = conditional_file("if-range").await;
+        let weak = format!("W/{}", etag);
+        let range = |if_range| [("range", "bytes=0-9"), ("if-range", if_range)];
+
+        let partial = (StatusCode::PARTIAL_CONTENT, 10);
+        assert_eq!(status(&path, &range(&etag)).await, partial);
+        assert_eq!(status(&path, &range(&last_modified)).await, partial);
+
+        // Stale or weak validators get the whole file.
+        let full = (StatusCode::OK, 100);
+        assert_eq!(status(&path, &range("\"other\"")).await, full);
+        assert_eq!(status(&path, &range(&weak)).await, full);
+        let before = "Thu, 01 Jan 2015 00:00:00 GMT";
+        assert_eq!(status(&path, &range(before)).await, full);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    /// Creates a directory containing `style.css`, and siblings of it with each of
+    /// the given extensions whose contents are the extension.
+    async fn precompressed_dir(name: &str, extensions: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("atium-precompressed-{}", name));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        tokio::fs::write(dir.join("style.css"), "original")
+            .await
+            .unwrap();
+        for extension in extensions {
+            let sibling = dir.join(format!("style.css.{}", extension));
+            tokio::fs::write(sibling, extension).await.unwrap();
+        }
+
+        dir
+    }
+
+    async fn serve_precompressed(dir: &Path, accept: Option<&str>) -> (Response, Bytes) {
+        let file = File::open(dir.join("style.css"))
+            .await
+            .unwrap()
+            .with_precompressed([Encoding::Brotli, Encoding::Gzip]);
+
+        match accept {
+            Some(accept) => serve(file, &[("accept-encoding", accept)]).await,
+            None => serve(file, &[]).await,
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_preferred_precompressed_file() {
+        let dir = precompressed_dir("preferred", &["gz", "br"]).await;
+
+        let cases = [
+            (Some("gzip"), Some("gzip"), "gz"),
+            (Some("br;q=0.5, gzip"), Some("gzip"), "gz"),
+            // Ties are broken by the order the encodings were given in.
+            (Some("gzip, br"), Some("br"), "br"),
+            (Some("*"), Some("br"), "br"),
+            (Some("br;q=0.5, identity"), None, "original"),
+            (Some("zstd"), None, "original"),
+            (None, None, "original"),
+        ];
+
+        for (accept, encoding, body) in &cases {
+            let (res, actual) = serve_precompressed(&dir, *accept).await;
+
+            let actual_encoding = res
+                .headers()
+                .get(CONTENT_ENCODING)
+                .map(|value| value.to_str().unwrap());
+            assert_eq!(actual_encoding, *encoding, "Accept-Encoding: {:?}", accept);
+            assert_eq!(
+                &actual[..],
+                body.as_bytes(),
+                "Accept-Encoding: {:?}",
+                accept
+            );
+            assert_eq!(res.headers()[VARY], "accept-encoding");
+            assert_eq!(res.headers()[CONTENT_TYPE], "text/css");
+            assert_eq!(res.headers()[CONTENT_LENGTH], body.len().to_string());
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn falls_back_when_sibling_is_missing() {
+        let dir = precompressed_dir("missing", &["gz"]).await;
+
+        let (res, body) = serve_precompressed(&dir, Some("br, gzip")).await;
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(&body[..], b"gz");
+
+        let (res, body) = serve_precompressed(&dir, Some("br")).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers()[VARY], "accept-encoding");
+        assert_eq!(&body[..], b"original");
+
+        // Without precompression enabled, the response doesn't vary.
+        let file = File::open(dir.join("style.css")).await.unwrap();
+        let (res, _) = serve(file, &[("accept-encoding", "gzip")]).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert!(res.headers().get(VARY).is_none());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn cache_policy_uses_innermost_directory() {
+        let policy = CachePolicy::new()
+            .with_dir(
+                "static",
+                CacheControl::new().with_max_age(Duration::from_secs(60)),
+            )
+            .with_dir("static/assets", CacheControl::new().with_immutable())
+            .with_default(CacheControl::new().with_no_cache());
+
+        let for_path = |path: &str| policy.for_path(Path::new(path)).cloned();
+
+        assert_eq!(
+            for_path("static/assets/app.js"),
+            Some(CacheControl::new().with_immutable())
+        );
+        assert_eq!(
+            for_path("static/index.html"),
+            Some(CacheControl::new().with_max_age(Duration::from_secs(60)))
+        );
+        assert_eq!(
+            for_path("static-other/index.html"),
+            Some(CacheControl::new().with_no_cache())
+        );
+        assert_eq!(CachePolicy::new().for_path(Path::new("static/a")), None);
+    }
+}
@@ -0,0 +1,226 @@
+use std::ops::RangeInclusive;
+
+/// The most ranges that will be served in one response. Requests for more are
+/// answered with the full representation, since many small ranges can make the
+/// response much larger than the representation itself
+/// ([RFC 7233 §6.1](https://tools.ietf.org/html/rfc7233#section-6.1)).
+const MAX_RANGES: usize = 32;
+
+/// The outcome of evaluating a `Range` header against a representation of a
+/// known length, as described in [RFC 7233](https://tools.ietf.org/html/rfc7233).
+#[derive(Debug, PartialEq)]
+pub(crate) enum ByteRanges {
+    /// The header should be ignored and the full representation sent.
+    Full,
+    /// One or more satisfiable ranges, sorted and with overlaps coalesced.
+    Satisfiable(Vec<RangeInclusive<u64>>),
+    /// None of the requested ranges overlap the representation.
+    Unsatisfiable,
+}
+
+impl ByteRanges {
+    pub(crate) fn parse(header: &str, total_length: u64) -> ByteRanges {
+        let specs = match split_unit(header) {
+            Some(specs) => specs,
+            None => return ByteRanges::Full,
+        };
+
+        let specs: Vec<_> = specs
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        // At least one range is required for the header to be valid.
+        if specs.is_empty() || specs.len() > MAX_RANGES {
+            return ByteRanges::Full;
+        }
+
+        let mut ranges = vec![];
+
+        for spec in specs {
+            let spec = match ByteRangeSpec::parse(spec) {
+                Some(spec) => spec,
+                // A syntactically invalid header is ignored entirely.
+                None => return ByteRanges::Full,
+            };
+
+            if let Some(range) = spec.resolve(total_length) {
+                ranges.push(range);
+            }
+        }
+
+        if ranges.is_empty() {
+            return ByteRanges::Unsatisfiable;
+        }
+
+        ranges.sort_by_key(|range| *range.start());
+
+        let mut coalesced: Vec<RangeInclusive<u64>> = Vec::with_capacity(ranges.len());
+
+        for range in ranges {
+            match coalesced.last_mut() {
+                Some(last) if *range.start() <= last.end().saturating_add(1) => {
+                    if range.end() > last.end() {
+                        *last = *last.start()..=*range.end();
+                    }
+                }
+                _ => coalesced.push(range),
+            }
+        }
+
+        ByteRanges::Satisfiable(coalesced)
+    }
+}
+
+/// Strips the `bytes=` prefix, returning `None` for any other range unit.
+fn split_unit(header: &str) -> Option<&str> {
+    let (unit, specs) = header.trim().split_once('=')?;
+
+    if unit.trim().eq_ignore_ascii_case("bytes") {
+        Some(specs)
+    } else {
+        None
+    }
+}
+
+enum ByteRangeSpec {
+    /// `first-last` or `first-`.
+    FromTo(u64, Option<u64>),
+    /// `-suffix`, i.e. the last `suffix` bytes.
+    Suffix(u64),
+}
+
+impl ByteRangeSpec {
+    fn parse(spec: &str) -> Option<ByteRangeSpec> {
+        let (first, last) = spec.split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+
+        if first.is_empty() {
+            return parse_digits(last).map(ByteRangeSpec::Suffix);
+        }
+
+        let first = parse_digits(first)?;
+        let last = match last {
+            "" => None,
+            last => Some(parse_digits(last)?),
+        };
+
+        match last {
+            Some(last) if last < first => None,
+            last => Some(ByteRangeSpec::FromTo(first, last)),
+        }
+    }
+
+    fn resolve(&self, total_length: u64) -> Option<RangeInclusive<u64>> {
+        if total_length == 0 {
+            return None;
+        }
+
+        let last_byte = total_length - 1;
+
+        match *self {
+            ByteRangeSpec::FromTo(first, _) if first > last_byte => None,
+            ByteRangeSpec::FromTo(first, last) => {
+                Some(first..=last.map_or(last_byte, |last| last.min(last_byte)))
+            }
+            ByteRangeSpec::Suffix(0) => None,
+            ByteRangeSpec::Suffix(n) => Some(total_length.saturating_sub(n)..=last_byte),
+        }
+    }
+}
+
+fn parse_digits(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(header: &str) -> ByteRanges {
+        ByteRanges::parse(header, 100)
+    }
+
+    fn satisfiable(ranges: &[RangeInclusive<u64>]) -> ByteRanges {
+        ByteRanges::Satisfiable(ranges.to_vec())
+    }
+
+    #[test]
+    fn closed_ranges() {
+        assert_eq!(parse("bytes=0-9"), satisfiable(&[0..=9]));
+        assert_eq!(parse("bytes=10-10"), satisfiable(&[10..=10]));
+        assert_eq!(parse("bytes=90-200"), satisfiable(&[90..=99]));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(parse("bytes=-10"), satisfiable(&[90..=99]));
+        assert_eq!(parse("bytes=-500"), satisfiable(&[0..=99]));
+        assert_eq!(parse("bytes=-0"), ByteRanges::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(parse("bytes=95-"), satisfiable(&[95..=99]));
+        assert_eq!(parse("bytes=0-"), satisfiable(&[0..=99]));
+    }
+
+    #[test]
+    fn out_of_bounds_ranges() {
+        assert_eq!(parse("bytes=100-"), ByteRanges::Unsatisfiable);
+        assert_eq!(parse("bytes=100-200, 300-400"), ByteRanges::Unsatisfiable);
+        assert_eq!(ByteRanges::parse("bytes=0-", 0), ByteRanges::Unsatisfiable);
+
+        // Unsatisfiable ranges are dropped if any others can be served.
+        assert_eq!(parse("bytes=200-300, 0-4"), satisfiable(&[0..=4]));
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_ranges_are_merged() {
+        assert_eq!(parse("bytes=0-10, 5-20"), satisfiable(&[0..=20]));
+        assert_eq!(parse("bytes=0-9, 10-19"), satisfiable(&[0..=19]));
+        assert_eq!(
+            parse("bytes=50-60, 0-4, 55-"),
+            satisfiable(&[0..=4, 50..=99])
+        );
+        assert_eq!(parse("bytes=0-4, -10"), satisfiable(&[0..=4, 90..=99]));
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        for header in [
+            "bytes=",
+            "bytes=,",
+            "bytes= , ",
+            "bytes=abc",
+            "bytes=10-5",
+            "bytes=1-2-3",
+            "bytes=-",
+            "bytes=+1-2",
+            "bytes=0-9, x",
+            "bytes 0-9",
+            "items=0-9",
+        ] {
+            assert_eq!(parse(header), ByteRanges::Full, "{}", header);
+        }
+    }
+
+    #[test]
+    fn too_many_ranges_are_ignored() {
+        let ranges = |n: u64| {
+            let specs: Vec<_> = (0..n).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+            format!("bytes={}", specs.join(","))
+        };
+
+        assert!(matches!(
+            parse(&ranges(MAX_RANGES as u64)),
+            ByteRanges::Satisfiable(ranges) if ranges.len() == MAX_RANGES
+        ));
+        assert_eq!(parse(&ranges(MAX_RANGES as u64 + 1)), ByteRanges::Full);
+    }
+}
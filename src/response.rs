@@ -1,4 +1,5 @@
 use headers::{Header, HeaderMapExt};
+use hyper::header::{HeaderName, HeaderValue, VARY};
 use hyper::{Body, HeaderMap, StatusCode};
 
 #[derive(Debug, Default)]
@@ -34,6 +35,10 @@ impl Response {
         self.0.headers_mut()
     }
 
+    pub fn header<H: Header>(&self) -> Option<H> {
+        self.0.headers().typed_get()
+    }
+
     pub fn set_header(&mut self, header: impl Header) {
         self.0.headers_mut().typed_insert(header);
     }
@@ -43,10 +48,31 @@ impl Response {
         self
     }
 
+    /// Adds a header name to the `Vary` header, unless it is already present.
+    pub fn add_vary(&mut self, name: HeaderName) {
+        let varies = self
+            .0
+            .headers()
+            .get_all(VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .any(|value| value == "*" || value.eq_ignore_ascii_case(name.as_str()));
+
+        if !varies {
+            self.0.headers_mut().append(VARY, HeaderValue::from(name));
+        }
+    }
+
     pub fn body(&self) -> &Body {
         self.0.body()
     }
 
+    pub fn take_body(&mut self) -> Body {
+        std::mem::take(self.0.body_mut())
+    }
+
     pub fn set_body(&mut self, body: impl Into<Body>) {
         *self.0.body_mut() = body.into();
     }
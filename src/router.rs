@@ -6,6 +6,7 @@ use async_trait::async_trait;
 use hyper::Method;
 use routefinder::Captures;
 
+use crate::handler::run_handler;
 use crate::{Handler, Request};
 
 #[derive(Default)]
@@ -13,8 +14,12 @@ pub struct Router {
     method_map: HashMap<Method, routefinder::Router<Arc<dyn Handler>>>,
 }
 
+#[derive(Clone)]
 struct MatchedPath(usize);
 
+#[derive(Clone)]
+struct MatchedRoute(String);
+
 #[async_trait]
 impl Handler for Router {
     async fn run(&self, mut req: crate::Request, next: &dyn crate::Next) -> Request {
@@ -31,11 +36,13 @@ impl Handler for Router {
             .get(req.method())
             .and_then(|r| r.best_match(&path[offset..]));
 
-        let (handler, params) = match m {
-            Some(val) => (val.handler(), val.captures()),
+        let m = match m {
+            Some(m) => m,
             None => return next.run(req).await,
         };
 
+        let (handler, spec, params) = (m.handler(), m.route(), m.captures());
+
         // Calculate how much of the path has been matched.
         // If this is a wildcard route, calculate the length of the matched part using
         // some simple pointer arithmetic.
@@ -47,10 +54,22 @@ impl Handler for Router {
 
         let params = params.into_owned();
 
-        req.set_ext(MatchedPath(start));
+        // Nested routes are recorded relative to the wildcard of the parent route, so
+        // join the two to get the full pattern.
+        let route = match req.take_ext::<MatchedRoute>() {
+            Some(MatchedRoute(parent)) => format!(
+                "{}{}",
+                parent.trim_end_matches('*').trim_end_matches('/'),
+                spec
+            ),
+            None => spec.to_string(),
+        };
+
+        req.set_cloneable_ext(MatchedPath(start));
+        req.set_cloneable_ext(MatchedRoute(route));
         req.set_ext(params);
 
-        handler.run(req, next).await
+        run_handler(handler.as_ref(), req, next).await
     }
 }
 
@@ -69,6 +88,19 @@ impl Router {
     pub fn route<'a, 'b>(&'a mut self, path: &'b str) -> Route<'a, 'b> {
         Route(self, path)
     }
+
+    /// Adds a route defined with one of the route attribute macros, e.g.
+    /// [`get`](crate::get).
+    pub fn service(&mut self, service: impl Service) -> &mut Self {
+        service.register(self);
+        self
+    }
+}
+
+/// A route that adds itself to a [`Router`]. This is implemented by the endpoints
+/// defined with the route attribute macros.
+pub trait Service {
+    fn register(self, router: &mut Router);
 }
 
 macro_rules! method_fn {
@@ -77,7 +109,7 @@ macro_rules! method_fn {
             self.0
                 .method_map
                 .entry(Method::$method)
-                .or_insert_with(Default::default)
+                .or_default()
                 .add(self.1, Arc::new(handler))
                 .expect("invalid path");
             self
@@ -116,7 +148,7 @@ impl<'a, 'b> Route<'a, 'b> {
             self.0
                 .method_map
                 .entry(method.clone())
-                .or_insert_with(Default::default)
+                .or_default()
                 .add(self.1, handler.clone())
                 .expect("invalid path");
         }
@@ -134,6 +166,8 @@ pub enum ParamError {
 }
 
 pub trait RouterRequestExt {
+    /// The pattern of the route that matched the request, e.g. `/users/:id`.
+    fn route_pattern(&self) -> Option<&str>;
     fn param_str(&self, name: &str) -> Result<&str, ParamError>;
     fn param<T: FromStr>(&self, name: &str) -> Result<T, ParamError>
     where
@@ -141,6 +175,10 @@ pub trait RouterRequestExt {
 }
 
 impl RouterRequestExt for Request {
+    fn route_pattern(&self) -> Option<&str> {
+        self.ext::<MatchedRoute>().map(|route| route.0.as_str())
+    }
+
     fn param_str(&self, name: &str) -> Result<&str, ParamError> {
         self.ext::<Captures>()
             .and_then(|params| params.get(name))
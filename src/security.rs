@@ -0,0 +1,387 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hyper::header::{
+    HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+    X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+
+use crate::{Handler, Next, Request};
+
+const PERMISSIONS_POLICY: &str = "permissions-policy";
+const CROSS_ORIGIN_OPENER_POLICY: &str = "cross-origin-opener-policy";
+const CROSS_ORIGIN_EMBEDDER_POLICY: &str = "cross-origin-embedder-policy";
+const CROSS_ORIGIN_RESOURCE_POLICY: &str = "cross-origin-resource-policy";
+
+/// The placeholder in a content security policy that is replaced with a fresh nonce
+/// for every request.
+pub const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+/// Middleware that adds security related headers to every response.
+///
+/// Headers that the response already has are left alone, so handlers can override
+/// them by setting them directly. Handlers can also replace or remove them by
+/// setting a [`SecurityOverrides`] extension on the request.
+///
+/// The defaults are:
+///
+/// - `Strict-Transport-Security: max-age=31536000; includeSubDomains`
+/// - `X-Content-Type-Options: nosniff`
+/// - `X-Frame-Options: DENY`
+/// - `Referrer-Policy: strict-origin-when-cross-origin`
+/// - `Cross-Origin-Opener-Policy: same-origin`
+/// - `Cross-Origin-Resource-Policy: same-origin`
+pub struct SecurityHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+    csp: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders::empty()
+            .with_hsts(Duration::from_secs(31536000), true, false)
+            .with_header(X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .with_frame_options("DENY")
+            .with_referrer_policy("strict-origin-when-cross-origin")
+            .with_cross_origin_opener_policy("same-origin")
+            .with_cross_origin_resource_policy("same-origin")
+    }
+}
+
+impl SecurityHeaders {
+    pub fn new() -> Self {
+        SecurityHeaders::default()
+    }
+
+    /// Creates a `SecurityHeaders` that doesn't set any headers.
+    pub fn empty() -> Self {
+        SecurityHeaders {
+            headers: vec![],
+            csp: None,
+        }
+    }
+
+    /// Sets a header to add to responses, replacing any previous value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is not a valid header value.
+    pub fn with_header(mut self, name: HeaderName, value: &str) -> Self {
+        let value = HeaderValue::from_str(value).expect("invalid header value");
+
+        match self.headers.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.headers.push((name, value)),
+        }
+
+        self
+    }
+
+    /// Stops a header from being added to responses.
+    pub fn without_header(mut self, name: HeaderName) -> Self {
+        if name == CONTENT_SECURITY_POLICY {
+            self.csp = None;
+        }
+
+        self.headers.retain(|(n, _)| *n != name);
+        self
+    }
+
+    pub fn with_hsts(self, max_age: Duration, include_subdomains: bool, preload: bool) -> Self {
+        let mut value = format!("max-age={}", max_age.as_secs());
+
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+
+        if preload {
+            value.push_str("; preload");
+        }
+
+        self.with_header(STRICT_TRANSPORT_SECURITY, &value)
+    }
+
+    /// Sets the `Content-Security-Policy` header. Any occurrences of
+    /// [`NONCE_PLACEHOLDER`] are replaced with a nonce generated for each request,
+    /// which handlers can get with [`SecurityRequestExt::csp_nonce`].
+    ///
+    /// ```
+    /// use atium::security::SecurityHeaders;
+    ///
+    /// let security =
+    ///     SecurityHeaders::new().with_content_security_policy("script-src 'nonce-{nonce}'");
+    /// ```
+    pub fn with_content_security_policy(mut self, policy: &str) -> Self {
+        HeaderValue::from_str(policy).expect("invalid header value");
+        self.csp = Some(policy.to_owned());
+        self
+    }
+
+    pub fn with_frame_options(self, value: &str) -> Self {
+        self.with_header(X_FRAME_OPTIONS, value)
+    }
+
+    pub fn with_referrer_policy(self, value: &str) -> Self {
+        self.with_header(REFERRER_POLICY, value)
+    }
+
+    pub fn with_permissions_policy(self, value: &str) -> Self {
+        self.with_header(HeaderName::from_static(PERMISSIONS_POLICY), value)
+    }
+
+    pub fn with_cross_origin_opener_policy(self, value: &str) -> Self {
+        self.with_header(HeaderName::from_static(CROSS_ORIGIN_OPENER_POLICY), value)
+    }
+
+    pub fn with_cross_origin_embedder_policy(self, value: &str) -> Self {
+        self.with_header(HeaderName::from_static(CROSS_ORIGIN_EMBEDDER_POLICY), value)
+    }
+
+    pub fn with_cross_origin_resource_policy(self, value: &str) -> Self {
+        self.with_header(HeaderName::from_static(CROSS_ORIGIN_RESOURCE_POLICY), value)
+    }
+}
+
+/// Per-request changes to the headers added by [`SecurityHeaders`], set as an
+/// extension on the request by a handler.
+#[derive(Clone, Debug, Default)]
+pub struct SecurityOverrides {
+    changes: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl SecurityOverrides {
+    pub fn new() -> Self {
+        SecurityOverrides::default()
+    }
+
+    /// Replaces the value of a header for this response.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.changes.push((name, Some(value)));
+        self
+    }
+
+    /// Stops a header from being added to this response.
+    pub fn without_header(mut self, name: HeaderName) -> Self {
+        self.changes.push((name, None));
+        self
+    }
+}
+
+#[derive(Clone)]
+struct CspNonce(String);
+
+pub trait SecurityRequestExt {
+    /// The nonce to use in `nonce` attributes of inline scripts and styles.
+    fn csp_nonce(&self) -> Option<&str>;
+}
+
+impl SecurityRequestExt for Request {
+    fn csp_nonce(&self) -> Option<&str> {
+        self.ext::<CspNonce>().map(|nonce| nonce.0.as_str())
+    }
+}
+
+#[async_trait]
+impl Handler for SecurityHeaders {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let csp = match &self.csp {
+            Some(csp) if csp.contains(NONCE_PLACEHOLDER) => {
+                let nonce = uuid::Uuid::new_v4().simple().to_string();
+                let csp = csp.replace(NONCE_PLACEHOLDER, &nonce);
+                req.set_cloneable_ext(CspNonce(nonce));
+                Some(csp)
+            }
+            csp => csp.clone(),
+        };
+
+        let mut req = next.run(req).await;
+        let overrides = req.take_ext::<SecurityOverrides>();
+
+        let res = match req.res_mut() {
+            Some(res) => res,
+            None => return req,
+        };
+
+        let csp = csp.map(|csp| {
+            (
+                CONTENT_SECURITY_POLICY,
+                HeaderValue::from_str(&csp).unwrap(),
+            )
+        });
+        let mut headers: Vec<_> = self.headers.iter().cloned().chain(csp).collect();
+
+        for (name, value) in overrides.map(|o| o.changes).unwrap_or_default() {
+            headers.retain(|(n, _)| *n != name);
+            if let Some(value) = value {
+                headers.push((name, value));
+            }
+        }
+
+        for (name, value) in headers {
+            if !res.headers().contains_key(&name) {
+                res.headers_mut().insert(name, value);
+            }
+        }
+
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use hyper::header::CONTENT_TYPE;
+    use hyper::{Body, StatusCode};
+
+    use super::*;
+    use crate::handler::NextFn;
+    use crate::Response;
+
+    /// Sends a request through `security` to `endpoint`, returning the response.
+    async fn run<F, Fut>(security: &SecurityHeaders, endpoint: F) -> Response
+    where
+        F: Fn(Request) -> Fut + Send + Sync,
+        Fut: Future<Output = Request> + Send,
+    {
+        let req = Request::new(hyper::Request::new(Body::empty()), None);
+        security
+            .run(req, &NextFn(endpoint))
+            .await
+            .take_res()
+            .unwrap()
+    }
+
+    async fn ok(mut req: Request) -> Request {
+        req.set_res(StatusCode::OK);
+        req
+    }
+
+    fn header<'a>(res: &'a Response, name: &str) -> Option<&'a str> {
+        res.headers().get(name).map(|value| value.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn sets_default_headers() {
+        let res = run(&SecurityHeaders::new(), ok).await;
+
+        let expected = [
+            (
+                "strict-transport-security",
+                "max-age=31536000; includeSubDomains",
+            ),
+            ("x-content-type-options", "nosniff"),
+            ("x-frame-options", "DENY"),
+            ("referrer-policy", "strict-origin-when-cross-origin"),
+            ("cross-origin-opener-policy", "same-origin"),
+            ("cross-origin-resource-policy", "same-origin"),
+        ];
+
+        for (name, value) in &expected {
+            assert_eq!(header(&res, name), Some(*value), "{}", name);
+        }
+
+        assert_eq!(res.headers().len(), expected.len());
+    }
+
+    #[tokio::test]
+    async fn configures_headers() {
+        let security = SecurityHeaders::new()
+            .with_frame_options("SAMEORIGIN")
+            .with_permissions_policy("camera=()")
+            .without_header(STRICT_TRANSPORT_SECURITY);
+
+        let res = run(&security, ok).await;
+
+        assert_eq!(header(&res, "x-frame-options"), Some("SAMEORIGIN"));
+        assert_eq!(header(&res, "permissions-policy"), Some("camera=()"));
+        assert_eq!(header(&res, "strict-transport-security"), None);
+
+        let res = run(&SecurityHeaders::empty(), ok).await;
+        assert!(res.headers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_headers_set_by_handlers() {
+        let endpoint = |mut req: Request| async move {
+            let mut res = Response::ok().with_header(headers::ContentType::html());
+            res.headers_mut()
+                .insert(X_FRAME_OPTIONS, HeaderValue::from_static("SAMEORIGIN"));
+            req.set_res(res);
+            req
+        };
+
+        let res = run(&SecurityHeaders::new(), endpoint).await;
+
+        assert_eq!(header(&res, "x-frame-options"), Some("SAMEORIGIN"));
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html");
+    }
+
+    #[tokio::test]
+    async fn applies_per_request_overrides() {
+        let endpoint = |mut req: Request| async move {
+            req.set_ext(
+                SecurityOverrides::new()
+                    .with_header(X_FRAME_OPTIONS, HeaderValue::from_static("SAMEORIGIN"))
+                    .with_header(
+                        CONTENT_SECURITY_POLICY,
+                        HeaderValue::from_static("frame-ancestors 'self'"),
+                    )
+                    .without_header(HeaderName::from_static(CROSS_ORIGIN_OPENER_POLICY)),
+            );
+            ok(req).await
+        };
+
+        let security = SecurityHeaders::new().with_content_security_policy("default-src 'self'");
+        let res = run(&security, endpoint).await;
+
+        assert_eq!(header(&res, "x-frame-options"), Some("SAMEORIGIN"));
+        assert_eq!(
+            header(&res, "content-security-policy"),
+            Some("frame-ancestors 'self'")
+        );
+        assert_eq!(header(&res, "cross-origin-opener-policy"), None);
+        assert_eq!(header(&res, "x-content-type-options"), Some("nosniff"));
+    }
+
+    #[tokio::test]
+    async fn substitutes_fresh_nonce() {
+        let security = SecurityHeaders::empty()
+            .with_content_security_policy("script-src 'nonce-{nonce}'; style-src 'nonce-{nonce}'");
+
+        // Responds with the nonce the handler was given.
+        let endpoint = |mut req: Request| async move {
+            let nonce = req.csp_nonce().unwrap().to_owned();
+            req.set_res(nonce);
+            req
+        };
+
+        let mut nonces = vec![];
+
+        for _ in 0..2 {
+            let mut res = run(&security, endpoint).await;
+            let body = hyper::body::to_bytes(res.take_body()).await.unwrap();
+            let nonce = String::from_utf8(body.to_vec()).unwrap();
+
+            assert_eq!(
+                header(&res, "content-security-policy").unwrap(),
+                format!("script-src 'nonce-{0}'; style-src 'nonce-{0}'", nonce)
+            );
+            nonces.push(nonce);
+        }
+
+        assert_ne!(nonces[0], nonces[1]);
+
+        // Policies without a placeholder don't get a nonce.
+        let security = SecurityHeaders::empty().with_content_security_policy("default-src 'self'");
+        let endpoint = |req: Request| async move {
+            assert!(req.csp_nonce().is_none());
+            ok(req).await
+        };
+        let res = run(&security, endpoint).await;
+        assert_eq!(
+            header(&res, "content-security-policy"),
+            Some("default-src 'self'")
+        );
+    }
+}
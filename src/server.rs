@@ -5,11 +5,12 @@ use std::time::Duration;
 
 use futures::future::{self, Either};
 use futures::FutureExt;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Server};
 use tokio::sync::Notify;
 
-use crate::handler::NextFn;
+use crate::handler::{run_handler, NextFn};
 use crate::{Handler, Request};
 
 pub use hyper::Error;
@@ -29,9 +30,25 @@ async fn service(
     req: Request,
     handler: Arc<impl Handler>,
 ) -> std::result::Result<hyper::Response<Body>, NoResponse> {
-    let mut req = handler
-        .run(req, &NextFn(|req: Request| async move { req }))
-        .await;
+    #[cfg(feature = "tracing")]
+    let (req, span, start) = {
+        let (req, span) = crate::trace::request_span(req);
+        (req, span, std::time::Instant::now())
+    };
+
+    let run = run_handler(
+        handler.as_ref(),
+        req,
+        &NextFn(|req: Request| async move { req }),
+    );
+
+    #[cfg(feature = "tracing")]
+    let run = tracing::Instrument::instrument(run, span.clone());
+
+    let mut req = run.await;
+
+    #[cfg(feature = "tracing")]
+    crate::trace::record_response(&span, &req, start.elapsed());
 
     let res = match req.take_res() {
         Some(res) => res,
@@ -55,11 +72,12 @@ pub async fn run(addr: impl Into<SocketAddr>, handler: impl Handler) -> Result<(
     let addr = addr.into();
     let handler = Arc::new(handler);
 
-    let make_svc = make_service_fn(|_| {
+    let make_svc = make_service_fn(|conn: &AddrStream| {
         let handler = handler.clone();
-        async {
+        let remote_addr = conn.remote_addr();
+        async move {
             Ok::<_, NoResponse>(service_fn(move |req| {
-                service(Request::new(req), handler.clone())
+                service(Request::new(req, Some(remote_addr)), handler.clone())
             }))
         }
     });
@@ -0,0 +1,603 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::cookies::{time::OffsetDateTime, Cookie, CookieJar, CookieRequestExt, Key, SameSite};
+use crate::{Handler, Next, Request, StatusCode};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error("the session middleware is not installed")]
+    NotInstalled,
+    #[error("session store error")]
+    Store(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// A session as persisted by a [`SessionStore`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    id: String,
+    data: BTreeMap<String, Value>,
+    created: u64,
+    refreshed: u64,
+    expires: Option<u64>,
+}
+
+impl SessionRecord {
+    fn new() -> Self {
+        let now = unix_now();
+
+        SessionRecord {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            data: BTreeMap::new(),
+            created: now,
+            refreshed: now,
+            expires: None,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created)
+    }
+
+    /// When the session expires, if it does. Stores can evict sessions after this.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires
+            .map(|expires| UNIX_EPOCH + Duration::from_secs(expires))
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Persists sessions between requests.
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    /// Loads the session that the value of a session cookie refers to.
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError>;
+
+    /// Saves a session, returning the value to send in the session cookie.
+    async fn save(&self, record: &SessionRecord) -> Result<String, SessionError>;
+
+    /// Deletes a session, e.g. after it was destroyed or its ID was rotated.
+    async fn destroy(&self, record: &SessionRecord) -> Result<(), SessionError>;
+}
+
+/// Keeps sessions in memory, keyed by their ID. Sessions are lost when the server
+/// restarts and aren't shared between processes.
+///
+/// Expired sessions are removed when they are loaded, and the rest are swept out
+/// at most once a minute, when a session is saved.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<MemorySessions>>,
+}
+
+#[derive(Default)]
+struct MemorySessions {
+    records: HashMap<String, SessionRecord>,
+    last_sweep: Option<tokio::time::Instant>,
+}
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        match sessions.records.get(cookie) {
+            Some(record) if record.is_expired(unix_now()) => {
+                sessions.records.remove(cookie);
+                Ok(None)
+            }
+            record => Ok(record.cloned()),
+        }
+    }
+
+    async fn save(&self, record: &SessionRecord) -> Result<String, SessionError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = tokio::time::Instant::now();
+
+        if sessions
+            .last_sweep
+            .is_none_or(|last_sweep| now >= last_sweep + SWEEP_INTERVAL)
+        {
+            let unix_now = unix_now();
+            sessions
+                .records
+                .retain(|_, record| !record.is_expired(unix_now));
+            sessions.last_sweep = Some(now);
+        }
+
+        sessions.records.insert(record.id.clone(), record.clone());
+
+        Ok(record.id.clone())
+    }
+
+    async fn destroy(&self, record: &SessionRecord) -> Result<(), SessionError> {
+        self.sessions.lock().unwrap().records.remove(&record.id);
+        Ok(())
+    }
+}
+
+/// Keeps sessions in the session cookie itself, signed so that clients can't tamper
+/// with them. Session data can be read by the client, and must fit in a cookie.
+pub struct CookieStore {
+    key: Key,
+}
+
+impl CookieStore {
+    pub fn new(key: Key) -> Self {
+        CookieStore { key }
+    }
+}
+
+const SIGNED_COOKIE_NAME: &str = "session";
+
+#[async_trait]
+impl SessionStore for CookieStore {
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError> {
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new(SIGNED_COOKIE_NAME, cookie.to_owned()));
+
+        let record = jar
+            .signed(&self.key)
+            .get(SIGNED_COOKIE_NAME)
+            .and_then(|cookie| serde_json::from_str(cookie.value()).ok());
+
+        Ok(record)
+    }
+
+    async fn save(&self, record: &SessionRecord) -> Result<String, SessionError> {
+        let json = serde_json::to_string(record).map_err(|err| SessionError::Store(err.into()))?;
+
+        let mut jar = CookieJar::new();
+        jar.signed_mut(&self.key)
+            .add(Cookie::new(SIGNED_COOKIE_NAME, json));
+
+        Ok(jar.get(SIGNED_COOKIE_NAME).unwrap().value().to_owned())
+    }
+
+    async fn destroy(&self, _: &SessionRecord) -> Result<(), SessionError> {
+        Ok(())
+    }
+}
+
+/// Middleware that provides sessions, accessed with [`SessionRequestExt::session`].
+///
+/// Sessions are only loaded from the store when a handler asks for them, and only
+/// saved when they change. A new session isn't created until something is inserted
+/// into it.
+///
+/// The session cookie is `HttpOnly`, `Secure` and `SameSite=Lax` by default.
+pub struct Session {
+    store: Arc<dyn SessionStore>,
+    cookie: Cookie<'static>,
+    idle_timeout: Option<Duration>,
+    absolute_timeout: Option<Duration>,
+}
+
+impl Session {
+    pub fn new(store: impl SessionStore) -> Self {
+        let cookie = Cookie::build(("session", ""))
+            .path("/")
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Lax)
+            .build();
+
+        Session {
+            store: Arc::new(store),
+            cookie,
+            idle_timeout: None,
+            absolute_timeout: None,
+        }
+    }
+
+    /// Sets the name of the session cookie. Defaults to `session`.
+    pub fn with_cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.cookie.set_name(name.into());
+        self
+    }
+
+    /// Sets the path of the session cookie. Defaults to `/`.
+    pub fn with_cookie_path(mut self, path: impl Into<String>) -> Self {
+        self.cookie.set_path(path.into());
+        self
+    }
+
+    pub fn with_cookie_domain(mut self, domain: impl Into<String>) -> Self {
+        self.cookie.set_domain(domain.into());
+        self
+    }
+
+    /// Sets whether the session cookie is only sent over HTTPS. Defaults to `true`.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.cookie.set_secure(secure);
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.cookie.set_same_site(same_site);
+        self
+    }
+
+    /// Expires sessions that haven't been used for the given duration.
+    ///
+    /// Saving a session on every request would be wasteful, so the expiry of an
+    /// unchanged session is only pushed back once half of this duration has passed
+    /// since it was last saved.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Expires sessions the given duration after they were created, regardless of
+    /// whether they are in use.
+    pub fn with_absolute_timeout(mut self, timeout: Duration) -> Self {
+        self.absolute_timeout = Some(timeout);
+        self
+    }
+
+    fn expires(&self, record: &SessionRecord) -> Option<u64> {
+        let idle = self
+            .idle_timeout
+            .map(|idle| record.refreshed + idle.as_secs());
+        let absolute = self
+            .absolute_timeout
+            .map(|absolute| record.created + absolute.as_secs());
+
+        match (idle, absolute) {
+            (Some(idle), Some(absolute)) => Some(idle.min(absolute)),
+            (idle, absolute) => idle.or(absolute),
+        }
+    }
+
+    fn needs_refresh(&self, data: &SessionData, now: u64) -> bool {
+        match self.idle_timeout {
+            Some(idle) if !data.is_new => {
+                now.saturating_sub(data.record.refreshed) >= idle.as_secs() / 2
+            }
+            _ => false,
+        }
+    }
+
+    async fn commit(&self, req: &mut Request, mut data: SessionData) -> Result<(), SessionError> {
+        if let Some(previous) = &data.previous {
+            self.store.destroy(previous).await?;
+        }
+
+        let now = unix_now();
+
+        if data.changed || self.needs_refresh(&data, now) {
+            data.record.refreshed = now;
+            data.record.expires = self.expires(&data.record);
+
+            let value = self.store.save(&data.record).await?;
+            self.set_cookie(req, Some(value), data.record.expires);
+        } else if data.previous.is_some() || data.stale_cookie {
+            self.set_cookie(req, None, None);
+        }
+
+        Ok(())
+    }
+
+    fn set_cookie(&self, req: &mut Request, value: Option<String>, expires: Option<u64>) {
+        let mut cookie = self.cookie.clone();
+
+        match value {
+            Some(value) => {
+                cookie.set_value(value);
+
+                if let Some(expires) = expires {
+                    cookie.set_expires(OffsetDateTime::from_unix_timestamp(expires as i64).ok());
+                }
+
+                req.cookies().add(cookie);
+            }
+            None => req.cookies().remove(cookie),
+        }
+    }
+}
+
+/// The session of the current request.
+pub struct SessionData {
+    record: SessionRecord,
+    is_new: bool,
+    changed: bool,
+    previous: Option<SessionRecord>,
+    /// Whether the request's session cookie refers to a session that has expired or
+    /// doesn't exist, so should be removed.
+    stale_cookie: bool,
+}
+
+impl SessionData {
+    fn new(record: SessionRecord, is_new: bool) -> Self {
+        SessionData {
+            record,
+            is_new,
+            changed: false,
+            previous: None,
+            stale_cookie: false,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.record.id
+    }
+
+    /// Gets a value, returning `None` if it doesn't exist or has a different type.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.record.data.get(key)?;
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    pub fn insert<T: Serialize>(&mut self, key: &str, value: T) -> serde_json::Result<()> {
+        let value = serde_json::to_value(value)?;
+        self.record.data.insert(key.to_owned(), value);
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if self.record.data.remove(key).is_some() {
+            self.changed = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if !self.record.data.is_empty() {
+            self.record.data.clear();
+            self.changed = true;
+        }
+    }
+
+    /// Gives the session a new ID while keeping its data. This should be done
+    /// whenever the user's privileges change, e.g. on login, to prevent session
+    /// fixation.
+    pub fn rotate_id(&mut self) {
+        if !self.is_new && self.previous.is_none() {
+            self.previous = Some(self.record.clone());
+        }
+
+        self.record.id = SessionRecord::new().id;
+        self.changed = true;
+    }
+
+    /// Deletes the session and its cookie. Values inserted afterwards are saved in a
+    /// new session.
+    pub fn destroy(&mut self) {
+        if !self.is_new && self.previous.is_none() {
+            self.previous = Some(self.record.clone());
+        }
+
+        self.record = SessionRecord::new();
+        self.is_new = true;
+        self.changed = false;
+    }
+}
+
+struct SessionState {
+    store: Arc<dyn SessionStore>,
+    cookie: Option<String>,
+    data: Option<SessionData>,
+}
+
+impl SessionState {
+    async fn load(&self) -> Result<SessionData, SessionError> {
+        let record = match &self.cookie {
+            Some(cookie) => self.store.load(cookie).await?,
+            None => None,
+        };
+
+        match record {
+            Some(record) if !record.is_expired(unix_now()) => Ok(SessionData::new(record, false)),
+            record => {
+                if let Some(record) = record {
+                    self.store.destroy(&record).await?;
+                }
+
+                let mut data = SessionData::new(SessionRecord::new(), true);
+                data.stale_cookie = self.cookie.is_some();
+                Ok(data)
+            }
+        }
+    }
+}
+
+#[async_trait]
+pub trait SessionRequestExt {
+    /// The session of the request, loading it from the store if necessary.
+    async fn session(&mut self) -> Result<&mut SessionData, SessionError>;
+}
+
+#[async_trait]
+impl SessionRequestExt for Request {
+    async fn session(&mut self) -> Result<&mut SessionData, SessionError> {
+        let state = self
+            .ext_mut::<SessionState>()
+            .ok_or(SessionError::NotInstalled)?;
+
+        if state.data.is_none() {
+            state.data = Some(state.load().await?);
+        }
+
+        Ok(state.data.as_mut().unwrap())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[async_trait]
+impl Handler for Session {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let cookie = req
+            .cookies()
+            .get(self.cookie.name())
+            .map(|cookie| cookie.value().to_owned());
+
+        req.set_ext(SessionState {
+            store: self.store.clone(),
+            cookie,
+            data: None,
+        });
+
+        let mut req = next.run(req).await;
+
+        let data = match req.take_ext::<SessionState>().and_then(|state| state.data) {
+            Some(data) => data,
+            None => return req,
+        };
+
+        if let Err(err) = self.commit(&mut req, data).await {
+            log::error!("failed to save session: {}", err);
+            req.set_res(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        crate::cookies::write_cookies(&mut req);
+
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::SET_COOKIE;
+    use hyper::Body;
+
+    use super::*;
+    use crate::cookies::Cookies;
+    use crate::handler::NextFn;
+
+    async fn set_cookies(handler: impl Handler) -> Vec<String> {
+        let endpoint = |mut req: Request| async move {
+            req.session().await.unwrap().insert("user", 1).unwrap();
+            req.set_res(StatusCode::OK);
+            req
+        };
+
+        let req = hyper::Request::new(Body::empty());
+        let req = (handler, endpoint)
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await;
+
+        req.res()
+            .unwrap()
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sets_cookie_without_cookies_middleware() {
+        let cookies = set_cookies(Session::new(MemoryStore::new())).await;
+        assert_eq!(cookies.len(), 1);
+        assert!(cookies[0].starts_with("session="));
+    }
+
+    #[tokio::test]
+    async fn sets_cookie_once_with_cookies_middleware() {
+        let cookies = set_cookies((Cookies::new(), Session::new(MemoryStore::new()))).await;
+        assert_eq!(cookies.len(), 1);
+        assert!(cookies[0].starts_with("session="));
+    }
+
+    /// A session that expired a second ago.
+    fn expired_record() -> SessionRecord {
+        let mut record = SessionRecord::new();
+        record.expires = Some(unix_now() - 1);
+        record
+    }
+
+    #[tokio::test]
+    async fn memory_store_expires_sessions_on_load() {
+        let store = MemoryStore::new();
+        let record = expired_record();
+        let cookie = store.save(&record).await.unwrap();
+
+        assert!(store.load(&cookie).await.unwrap().is_none());
+        assert!(store.sessions.lock().unwrap().records.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn memory_store_sweeps_expired_sessions() {
+        let store = MemoryStore::new();
+        store.save(&SessionRecord::new()).await.unwrap();
+        store.save(&expired_record()).await.unwrap();
+
+        // Not until a minute has passed since the last sweep.
+        tokio::time::advance(Duration::from_secs(30)).await;
+        store.save(&SessionRecord::new()).await.unwrap();
+        assert_eq!(store.sessions.lock().unwrap().records.len(), 3);
+
+        // Then the expired session is replaced by the new one.
+        tokio::time::advance(Duration::from_secs(30)).await;
+        store.save(&SessionRecord::new()).await.unwrap();
+        assert_eq!(store.sessions.lock().unwrap().records.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn removes_cookie_of_expired_session() {
+        let store = MemoryStore::new();
+        let cookie = store.save(&expired_record()).await.unwrap();
+
+        let endpoint = |mut req: Request| async move {
+            assert!(req.session().await.unwrap().get::<u32>("user").is_none());
+            req.set_res(StatusCode::OK);
+            req
+        };
+
+        let req = hyper::Request::get("/")
+            .header(hyper::header::COOKIE, format!("session={}", cookie))
+            .body(Body::empty())
+            .unwrap();
+        let req = (Session::new(store), endpoint)
+            .run(Request::new(req, None), &NextFn(|req| async move { req }))
+            .await;
+
+        let set_cookie = req.res().unwrap().headers()[SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.starts_with("session=;"), "{}", set_cookie);
+        assert!(set_cookie.contains("Max-Age=0"), "{}", set_cookie);
+    }
+
+    #[test]
+    fn refresh_time_in_the_future_does_not_need_refresh() {
+        let session = Session::new(MemoryStore::new()).with_idle_timeout(Duration::from_secs(60));
+        let mut record = SessionRecord::new();
+        record.refreshed += 3600;
+
+        let data = SessionData {
+            record,
+            is_new: false,
+            changed: false,
+            previous: None,
+            stale_cookie: false,
+        };
+
+        assert!(!session.needs_refresh(&data, unix_now()));
+    }
+}
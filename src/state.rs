@@ -7,7 +7,7 @@ pub struct State<T>(pub T);
 #[async_trait]
 impl<T: Clone + Send + Sync + 'static> Handler for State<T> {
     async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
-        req.set_ext(self.0.clone());
+        req.set_cloneable_ext(self.0.clone());
         next.run(req).await
     }
 }
@@ -0,0 +1,200 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use hyper::StatusCode;
+
+use crate::{Handler, Next, Request};
+
+/// Middleware that gives up on requests that take too long, responding with
+/// `504 Gateway Timeout` by default.
+///
+/// The deadline is available to handlers with [`TimeoutRequestExt::deadline`], so
+/// they can budget calls to other services.
+///
+/// A `Timeout` overrides the deadline of any outer `Timeout`, so routes can be given
+/// a shorter or longer timeout than the default by adding one to them.
+///
+/// When a request times out, the response is generated from a copy of the request
+/// as it was before it was passed on, so extensions set by later handlers are lost.
+/// Those set by earlier middleware, like the request ID and the matched route of an
+/// outer router, are kept.
+pub struct Timeout {
+    duration: Duration,
+    status: StatusCode,
+}
+
+impl Timeout {
+    pub fn new(duration: Duration) -> Self {
+        Timeout {
+            duration,
+            status: StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Sets the status of the response sent when a request times out, e.g.
+    /// `503 Service Unavailable`.
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// The deadline shared by all of the `Timeout`s a request passes through, so that
+/// inner ones can move it. Kept on tokio's clock so that it follows paused time.
+#[derive(Clone)]
+struct Deadline(Arc<Mutex<tokio::time::Instant>>);
+
+impl Deadline {
+    fn get(&self) -> tokio::time::Instant {
+        *self.0.lock().unwrap()
+    }
+
+    fn set(&self, deadline: tokio::time::Instant) {
+        *self.0.lock().unwrap() = deadline;
+    }
+}
+
+pub trait TimeoutRequestExt {
+    /// The time by which the request must be handled, if it has a timeout.
+    fn deadline(&self) -> Option<Instant>;
+
+    /// The time left until the request's deadline, if it has a timeout.
+    fn time_remaining(&self) -> Option<Duration>;
+}
+
+impl TimeoutRequestExt for Request {
+    fn deadline(&self) -> Option<Instant> {
+        self.ext::<Deadline>()
+            .map(|deadline| deadline.get().into_std())
+    }
+
+    fn time_remaining(&self) -> Option<Duration> {
+        self.ext::<Deadline>().map(|deadline| {
+            deadline
+                .get()
+                .saturating_duration_since(tokio::time::Instant::now())
+        })
+    }
+}
+
+#[async_trait]
+impl Handler for Timeout {
+    async fn run(&self, mut req: Request, next: &dyn Next) -> Request {
+        let deadline = match req.ext::<Deadline>() {
+            Some(deadline) => deadline.clone(),
+            None => {
+                let now = tokio::time::Instant::now();
+                let deadline = Deadline(Arc::new(Mutex::new(now)));
+                req.set_cloneable_ext(deadline.clone());
+                deadline
+            }
+        };
+
+        deadline.set(tokio::time::Instant::now() + self.duration);
+
+        let head = req.clone_head();
+        let run = next.run(req);
+        futures::pin_mut!(run);
+
+        // An inner `Timeout` may have moved the deadline while we were waiting.
+        loop {
+            match tokio::time::timeout_at(deadline.get(), &mut run).await {
+                Ok(req) => return req,
+                Err(_) if tokio::time::Instant::now() >= deadline.get() => break,
+                Err(_) => {}
+            }
+        }
+
+        log::warn!(
+            "request to {} timed out after {:?}",
+            head.uri().path(),
+            self.duration
+        );
+
+        let mut req = head;
+        req.set_res(self.status);
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hyper::header::USER_AGENT;
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::NextFn;
+    use crate::logger::{LogFormat, LogRecord, Logger};
+    use crate::request_id::RequestId;
+
+    async fn sleep(req: Request) -> Request {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        req
+    }
+
+    fn request() -> Request {
+        let req = hyper::Request::get("/slow")
+            .header(USER_AGENT, "test")
+            .body(Body::empty())
+            .unwrap();
+        Request::new(req, None)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timed_out_requests_keep_earlier_extensions() {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let sink = {
+            let lines = lines.clone();
+            move |_: &LogRecord, line: &str| lines.lock().unwrap().push(line.to_owned())
+        };
+
+        let logger = Logger::new()
+            .with_format(LogFormat::custom("{status} {request_id} {user_agent}").unwrap())
+            .with_sink(sink);
+        let request_id = RequestId::new().with_generator(|| "abc".to_owned());
+        let timeout = Timeout::new(Duration::from_secs(1));
+
+        let req = (logger, (request_id, (timeout, sleep)))
+            .run(request(), &NextFn(|req| async move { req }))
+            .await;
+
+        assert_eq!(req.res().unwrap().status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(req.res().unwrap().headers()["x-request-id"], "abc");
+        assert_eq!(*lines.lock().unwrap(), ["504 abc test"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inner_timeout_overrides_outer_deadline() {
+        let start = tokio::time::Instant::now();
+
+        // The inner timeout extends the deadline beyond the outer one.
+        let handler = (
+            Timeout::new(Duration::from_secs(1)),
+            (Timeout::new(Duration::from_secs(10)), sleep),
+        );
+        let req = handler
+            .run(request(), &NextFn(|req| async move { req }))
+            .await;
+
+        assert_eq!(req.res().unwrap().status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(start.elapsed() >= Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(11));
+
+        // And shortens it.
+        let start = tokio::time::Instant::now();
+        let handler = (
+            Timeout::new(Duration::from_secs(30)),
+            (Timeout::new(Duration::from_secs(2)), sleep),
+        );
+        let req = handler
+            .run(request(), &NextFn(|req| async move { req }))
+            .await;
+
+        assert_eq!(req.res().unwrap().status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,251 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use tracing::field::Empty;
+use tracing::Span;
+
+use crate::request_id::RequestIdRequestExt;
+use crate::router::RouterRequestExt;
+use crate::Request;
+
+/// A parsed W3C [`traceparent`](https://www.w3.org/TR/trace-context/#traceparent-header)
+/// header.
+///
+/// The trace and parent span IDs are recorded as the `trace_id` and `parent_span_id`
+/// fields of the request span. `tracing` has no notion of remote parents, so to make
+/// the span a child of the caller's span in a distributed tracing system, register
+/// a hook with [`set_remote_parent`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceParent {
+    trace_id: String,
+    parent_id: String,
+    flags: u8,
+}
+
+impl TraceParent {
+    /// The 32 character hex ID of the whole trace.
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// The 16 character hex ID of the caller's span.
+    pub fn parent_id(&self) -> &str {
+        &self.parent_id
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid traceparent header")]
+pub struct InvalidTraceParent;
+
+impl FromStr for TraceParent {
+    type Err = InvalidTraceParent;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn is_hex(s: &str, len: usize) -> bool {
+            s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        }
+
+        fn is_zero(s: &str) -> bool {
+            s.bytes().all(|b| b == b'0')
+        }
+
+        let mut parts = s.trim().split('-');
+        let (version, trace_id, parent_id, flags) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(v), Some(t), Some(p), Some(f)) => (v, t, p, f),
+                _ => return Err(InvalidTraceParent),
+            };
+
+        // Version ff is forbidden, and only version 00 may not have trailing fields.
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return Err(InvalidTraceParent);
+        }
+
+        if !is_hex(trace_id, 32) || is_zero(trace_id) {
+            return Err(InvalidTraceParent);
+        }
+
+        if !is_hex(parent_id, 16) || is_zero(parent_id) || !is_hex(flags, 2) {
+            return Err(InvalidTraceParent);
+        }
+
+        Ok(TraceParent {
+            trace_id: trace_id.to_owned(),
+            parent_id: parent_id.to_owned(),
+            flags: u8::from_str_radix(flags, 16).unwrap(),
+        })
+    }
+}
+
+type RemoteParentFn = Box<dyn Fn(&Span, &TraceParent) + Send + Sync>;
+
+static REMOTE_PARENT: OnceLock<RemoteParentFn> = OnceLock::new();
+
+/// Sets the function that makes each request span a child of the remote span its
+/// `traceparent` header refers to. It is only called for requests with a valid
+/// header.
+///
+/// With `tracing-opentelemetry`, this builds an OpenTelemetry `Context` from the
+/// trace and parent span IDs and passes it to `OpenTelemetrySpanExt::set_parent`.
+///
+/// # Panics
+///
+/// Panics if a function has already been set.
+pub fn set_remote_parent(f: impl Fn(&Span, &TraceParent) + Send + Sync + 'static) {
+    if REMOTE_PARENT.set(Box::new(f)).is_err() {
+        panic!("remote parent function already set");
+    }
+}
+
+pub trait TraceRequestExt {
+    /// The trace context the request was sent with, if any.
+    fn trace_parent(&self) -> Option<&TraceParent>;
+}
+
+impl TraceRequestExt for Request {
+    fn trace_parent(&self) -> Option<&TraceParent> {
+        self.ext::<TraceParent>()
+    }
+}
+
+/// Creates the span that a request is handled in, storing its trace context in the
+/// request.
+pub(crate) fn request_span(mut req: Request) -> (Request, Span) {
+    let trace_parent = req
+        .headers()
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<TraceParent>().ok());
+
+    let span = tracing::info_span!(
+        "request",
+        http.method = %req.method(),
+        http.target = %req.uri(),
+        http.flavor = ?req.version(),
+        http.route = Empty,
+        http.status_code = Empty,
+        latency_ms = Empty,
+        request_id = Empty,
+        trace_id = Empty,
+        parent_span_id = Empty,
+    );
+
+    if let Some(trace_parent) = trace_parent {
+        span.record("trace_id", trace_parent.trace_id());
+        span.record("parent_span_id", trace_parent.parent_id());
+
+        if let Some(set_parent) = REMOTE_PARENT.get() {
+            set_parent(&span, &trace_parent);
+        }
+
+        req.set_cloneable_ext(trace_parent);
+    }
+
+    (req, span)
+}
+
+/// Records the outcome of a request on its span.
+pub(crate) fn record_response(span: &Span, req: &Request, latency: Duration) {
+    if let Some(route) = req.route_pattern() {
+        span.record("http.route", route);
+    }
+
+    if let Some(res) = req.res() {
+        span.record("http.status_code", res.status().as_u16());
+    }
+
+    if let Some(request_id) = req.request_id() {
+        span.record("request_id", request_id);
+    }
+
+    span.record("latency_ms", latency.as_secs_f64() * 1000.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyper::Body;
+
+    use super::*;
+
+    const VALID: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parses_traceparent() {
+        let trace_parent = VALID.parse::<TraceParent>().unwrap();
+
+        assert_eq!(trace_parent.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(trace_parent.parent_id(), "00f067aa0ba902b7");
+        assert_eq!(trace_parent.flags(), 1);
+        assert!(trace_parent.is_sampled());
+
+        // Later versions may add fields.
+        let later = "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra";
+        assert!(!later.parse::<TraceParent>().unwrap().is_sampled());
+    }
+
+    #[test]
+    fn rejects_invalid_traceparent() {
+        let invalid = [
+            // Version ff is forbidden.
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            // Version 00 has exactly four fields.
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            // All-zero IDs are invalid.
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            // Bad lengths.
+            "0-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            // Hex must be lowercase.
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00F067AA0BA902B7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-0A",
+            "",
+        ];
+
+        for value in &invalid {
+            assert!(value.parse::<TraceParent>().is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn sets_remote_parent() {
+        let parents = Arc::new(Mutex::new(vec![]));
+        set_remote_parent({
+            let parents = parents.clone();
+            move |_: &Span, trace_parent: &TraceParent| {
+                parents.lock().unwrap().push(trace_parent.clone())
+            }
+        });
+
+        let request = |traceparent: &str| {
+            let req = hyper::Request::get("/")
+                .header("traceparent", traceparent)
+                .body(Body::empty())
+                .unwrap();
+            request_span(Request::new(req, None)).0
+        };
+
+        let req = request(VALID);
+        let invalid = request("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+
+        assert_eq!(req.trace_parent(), Some(&VALID.parse().unwrap()));
+        assert_eq!(invalid.trace_parent(), None);
+        assert_eq!(*parents.lock().unwrap(), [VALID.parse().unwrap()]);
+    }
+}
@@ -0,0 +1,6 @@
+use atium::Request;
+
+#[atium::endpoint(krate = "atium")]
+async fn index(_: &mut Request) {}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use atium::{endpoint, Request};
+
+#[endpoint]
+fn index(_: &mut Request) {}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use atium::{get, Request};
+
+struct Api;
+
+impl Api {
+    #[get("/")]
+    async fn index(&self, _: &mut Request) {}
+}
+
+#[get("/")]
+async fn generic<T>(_: &mut Request) {}
+
+fn main() {}
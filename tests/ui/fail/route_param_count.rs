@@ -0,0 +1,14 @@
+use atium::extract::Path;
+use atium::get;
+
+#[get("/orgs/:org/users/:id")]
+async fn get_user(Path((org, id, extra)): Path<(String, u64, u8)>) -> String {
+    format!("{} {} {}", org, id, extra)
+}
+
+#[get("/orgs/:org/users/:id")]
+async fn get_id(Path(id): Path<u64>) -> String {
+    id.to_string()
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use atium::{get, Request};
+
+#[get("users")]
+async fn relative(_: &mut Request) {}
+
+#[get("/users/:id/:id")]
+async fn duplicate(_: &mut Request) {}
+
+#[get(users)]
+async fn not_a_string(_: &mut Request) {}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use atium::{endpoint, Request};
+
+struct Api;
+
+impl Api {
+    #[endpoint]
+    async fn index(self, _: &mut Request) {}
+}
+
+fn main() {}
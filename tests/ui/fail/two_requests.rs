@@ -0,0 +1,6 @@
+use atium::{endpoint, Request};
+
+#[endpoint]
+async fn index(_: &mut Request, _: &Request) {}
+
+fn main() {}
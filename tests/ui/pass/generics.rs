@@ -0,0 +1,33 @@
+#![deny(missing_docs)]
+//! Generic endpoints, with attributes.
+
+use std::fmt::Display;
+
+use atium::extract::State;
+use atium::{endpoint, Request};
+
+/// Responds with the state.
+#[endpoint]
+pub async fn show<T>(State(state): State<T>, _: &mut Request) -> String
+where
+    T: Display + Clone + Send + Sync + 'static,
+{
+    state.to_string()
+}
+
+/// Responds with a constant.
+#[endpoint]
+pub async fn constant<const N: usize>() -> String {
+    N.to_string()
+}
+
+#[cfg(any())]
+#[endpoint]
+pub async fn disabled(_: DoesNotExist) {}
+
+fn main() {
+    let _ = atium::router::Router::new().with(|r| {
+        r.route("/show").get(show::<u32>);
+        r.route("/constant").get(constant::<3>);
+    });
+}
@@ -0,0 +1,33 @@
+use atium::extract::Path;
+use atium::{async_trait, endpoint, Handler, Next, Request};
+
+struct Greeter {
+    greeting: String,
+}
+
+impl Greeter {
+    /// Greets someone.
+    #[endpoint]
+    pub async fn greet(&self, Path(name): Path<String>) -> String {
+        format!("{}, {}!", self.greeting, name)
+    }
+}
+
+#[async_trait]
+impl Handler for Greeter {
+    async fn run(&self, req: Request, _: &dyn Next) -> Request {
+        self.greet(req).await
+    }
+}
+
+fn main() {
+    let greeter = Greeter {
+        greeting: "hello".to_owned(),
+    };
+
+    let _ = atium::router::Router::new().with(move |r| {
+        r.route("/:name").get(Greeter {
+            greeting: greeter.greeting.clone(),
+        });
+    });
+}
@@ -0,0 +1,23 @@
+// `Request` isn't in scope, and atium is only available as `web`.
+mod api {
+    use ::atium as web;
+
+    use web::extract::Path;
+
+    #[web::endpoint(crate = web)]
+    pub async fn hello(Path(name): Path<String>) -> String {
+        format!("hello, {}!", name)
+    }
+
+    #[web::get("/users/:id", crate = "web")]
+    pub async fn get_user(Path(id): Path<u64>, req: &web::Request) -> String {
+        format!("{} {}", req.uri(), id)
+    }
+}
+
+fn main() {
+    let _ = atium::router::Router::new().with(|r| {
+        r.route("/hello/:name").get(api::hello);
+        r.service(api::get_user);
+    });
+}
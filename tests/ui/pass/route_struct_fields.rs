@@ -0,0 +1,40 @@
+use atium::extract::Path;
+use atium::{get, Request};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct UserPath {
+    org: String,
+    #[serde(rename = "user_id")]
+    id: u64,
+}
+
+#[get("/orgs/:org/users/:user_id")]
+async fn get_user(Path(path): Path<UserPath>) -> String {
+    format!("{} {}", path.org, path.id)
+}
+
+mod other {
+    /// An extractor that isn't atium's `Path`, so isn't checked against the route.
+    pub struct Path<T>(pub T);
+
+    #[async_trait::async_trait]
+    impl<T: Default + Send> atium::extract::FromRequest for Path<T> {
+        type Rejection = atium::StatusCode;
+
+        async fn from_request(_: &mut atium::Request) -> Result<Self, Self::Rejection> {
+            Ok(Path(T::default()))
+        }
+    }
+}
+
+#[get("/orgs/:org/users/:id")]
+async fn get_other(other::Path(id): other::Path<u64>, _: &mut Request) -> String {
+    id.to_string()
+}
+
+fn main() {
+    let _ = atium::router::Router::new().with(|r| {
+        r.service(get_user).service(get_other);
+    });
+}
@@ -0,0 +1,35 @@
+use atium::extract::{Json, Path, Query};
+use atium::{delete, get, post, Request};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct UserPath {
+    org: String,
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    page: Option<u32>,
+}
+
+#[get("/orgs/:org/users/:id")]
+async fn get_user(Path(path): Path<UserPath>, Query(page): Query<Page>) -> String {
+    format!("{} {} {:?}", path.org, path.id, page.page)
+}
+
+#[post("/orgs/:org/users")]
+async fn create_user(Path(org): Path<String>, Json(user): Json<serde_json::Value>) -> String {
+    format!("{} {}", org, user)
+}
+
+#[delete("/orgs/:org/users/:id")]
+async fn delete_user(Path((org, id)): Path<(String, u64)>, _: &mut Request) -> String {
+    format!("{} {}", org, id)
+}
+
+fn main() {
+    let _ = atium::router::Router::new().with(|r| {
+        r.service(get_user).service(create_user).service(delete_user);
+    });
+}
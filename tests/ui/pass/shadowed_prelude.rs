@@ -0,0 +1,38 @@
+// Items from the prelude are shadowed, as with `use anyhow::Ok;`.
+#![allow(dead_code, non_snake_case)]
+
+use atium::extract::Path;
+use atium::{endpoint, get};
+
+fn Ok<T>(value: T) -> Result<T, ()> {
+    Result::Ok(value)
+}
+
+fn Err<T>(value: T) -> Result<(), T> {
+    Result::Err(value)
+}
+
+struct Box;
+struct Option;
+struct Some;
+struct None;
+struct String;
+trait Send {}
+trait Sync {}
+
+#[endpoint]
+async fn hello(Path(name): Path<std::string::String>) -> std::string::String {
+    format!("hello, {}!", name)
+}
+
+#[get("/users/:id")]
+async fn get_user(Path(id): Path<u64>) -> std::string::String {
+    id.to_string()
+}
+
+fn main() {
+    let _ = atium::router::Router::new().with(|r| {
+        r.route("/hello/:name").get(hello);
+        r.service(get_user);
+    });
+}